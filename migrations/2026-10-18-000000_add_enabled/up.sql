@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE actions
+ADD COLUMN enabled boolean NOT NULL DEFAULT true;
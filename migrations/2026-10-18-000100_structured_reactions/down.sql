@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+-- Refuse rather than lose rules whose emoji don't fit in a single character
+DO $$
+BEGIN
+  IF EXISTS (SELECT 1 FROM actions, unnest(reactions) AS r WHERE char_length(r) > 1) THEN
+    RAISE EXCEPTION 'Some rules have multi-codepoint or custom emoji, edit or remove them first';
+  END IF;
+END $$;
+
+ALTER TABLE actions
+ALTER COLUMN reactions TYPE char(1)[] USING reactions::char(1)[];
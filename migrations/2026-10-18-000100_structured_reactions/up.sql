@@ -0,0 +1,5 @@
+-- Your SQL goes here
+-- Existing rows keep one codepoint per entry, the bot rejoins them into whole
+-- emoji once this has run, see `regroup_legacy_reactions`
+ALTER TABLE actions
+ALTER COLUMN reactions TYPE text[] USING reactions::text[];
@@ -0,0 +1,6 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE actions
+DROP COLUMN created_by,
+DROP COLUMN created_at,
+DROP COLUMN hit_count,
+DROP COLUMN last_fired_at;
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+ALTER TABLE actions
+ADD COLUMN created_by bigint,
+ADD COLUMN created_at timestamp,
+ADD COLUMN hit_count bigint NOT NULL DEFAULT 0,
+ADD COLUMN last_fired_at timestamp;
@@ -0,0 +1,19 @@
+CREATE TABLE archived_actions (
+  archive_id BIGSERIAL PRIMARY KEY,
+  action_id BIGINT NOT NULL,
+  guild_id BIGINT NOT NULL,
+  user_id BIGINT,
+  regex TEXT,
+  reactions text[] NOT NULL,
+  expiration timestamp,
+  enabled boolean NOT NULL,
+  created_by bigint,
+  created_at timestamp,
+  hit_count bigint NOT NULL,
+  last_fired_at timestamp,
+  reason TEXT NOT NULL CHECK (reason IN ('expired', 'removed', 'edited')),
+  archived_by bigint,
+  archived_at timestamp NOT NULL
+);
+
+CREATE INDEX archived_actions_guild_id ON archived_actions (guild_id);
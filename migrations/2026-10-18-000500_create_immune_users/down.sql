@@ -0,0 +1 @@
+DROP TABLE immune_users
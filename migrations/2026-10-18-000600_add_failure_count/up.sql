@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE actions
+ADD COLUMN failure_count bigint NOT NULL DEFAULT 0;
@@ -0,0 +1,11 @@
+-- SQLite equivalent of the postgres migrations. Reactions are stored space
+-- separated and expiration as seconds since the unix epoch.
+CREATE TABLE actions (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  guild_id BIGINT NOT NULL,
+  user_id BIGINT,
+  regex TEXT,
+  reactions TEXT NOT NULL,
+  expiration BIGINT,
+  enabled BOOLEAN NOT NULL DEFAULT 1
+);
@@ -0,0 +1,4 @@
+ALTER TABLE actions DROP COLUMN last_fired_at;
+ALTER TABLE actions DROP COLUMN hit_count;
+ALTER TABLE actions DROP COLUMN created_at;
+ALTER TABLE actions DROP COLUMN created_by;
@@ -0,0 +1,4 @@
+ALTER TABLE actions ADD COLUMN created_by BIGINT;
+ALTER TABLE actions ADD COLUMN created_at BIGINT;
+ALTER TABLE actions ADD COLUMN hit_count BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE actions ADD COLUMN last_fired_at BIGINT;
@@ -0,0 +1,19 @@
+CREATE TABLE archived_actions (
+  archive_id INTEGER PRIMARY KEY AUTOINCREMENT,
+  action_id BIGINT NOT NULL,
+  guild_id BIGINT NOT NULL,
+  user_id BIGINT,
+  regex TEXT,
+  reactions TEXT NOT NULL,
+  expiration BIGINT,
+  enabled BOOLEAN NOT NULL,
+  created_by BIGINT,
+  created_at BIGINT,
+  hit_count BIGINT NOT NULL,
+  last_fired_at BIGINT,
+  reason TEXT NOT NULL CHECK (reason IN ('expired', 'removed', 'edited')),
+  archived_by BIGINT,
+  archived_at BIGINT NOT NULL
+);
+
+CREATE INDEX archived_actions_guild_id ON archived_actions (guild_id);
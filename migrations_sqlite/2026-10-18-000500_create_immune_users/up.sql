@@ -0,0 +1,7 @@
+CREATE TABLE immune_users (
+  guild_id BIGINT NOT NULL,
+  user_id BIGINT NOT NULL,
+  added_by BIGINT,
+  added_at BIGINT NOT NULL,
+  PRIMARY KEY (guild_id, user_id)
+);
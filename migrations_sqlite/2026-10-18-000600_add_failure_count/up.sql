@@ -0,0 +1 @@
+ALTER TABLE actions ADD COLUMN failure_count BIGINT NOT NULL DEFAULT 0;
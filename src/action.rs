@@ -1,8 +1,10 @@
 use crate::schema::actions;
 use diesel::{Insertable, Queryable};
-use std::time::SystemTime;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 
-#[derive(Queryable, Insertable, Debug)]
+#[derive(Queryable, Insertable, Debug, Clone)]
 #[diesel(table_name = actions)]
 pub struct Action {
     pub id: i64,
@@ -11,4 +13,217 @@ pub struct Action {
     pub regex: Option<String>,
     pub reactions: Vec<String>,
     pub expiration: Option<SystemTime>,
+    pub enabled: bool,
+    pub created_by: Option<i64>,
+    pub created_at: Option<SystemTime>,
+    pub hit_count: i64,
+    pub last_fired_at: Option<SystemTime>,
+    /// Reactions that failed in a row because discord didn't know the emoji.
+    pub failure_count: i64,
+}
+
+impl Action {
+    /// Mention for the targeted user, or a placeholder if the rule applies to everyone.
+    pub fn user_mention(&self) -> String {
+        match self.user_id {
+            Some(uid) => format!("<@{}>", uid),
+            None => "anyone".to_string(),
+        }
+    }
+
+    /// Short one line description of the rule, e.g. `@1234 /bread/ 🍞`.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![match self.user_id {
+            Some(uid) => format!("@{}", uid),
+            None => "anyone".to_string(),
+        }];
+        if let Some(regex) = &self.regex {
+            parts.push(format!("/{}/", regex));
+        }
+        parts.push(self.reactions.concat());
+        if !self.enabled {
+            parts.push("(paused)".to_string());
+        }
+        parts.join(" ")
+    }
+
+    /// Who created the rule and how often it has fired, using discord's
+    /// relative timestamps.
+    pub fn stats(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(uid) = self.created_by {
+            parts.push(format!("Created by <@{}>", uid));
+        }
+        if let Some(created_at) = self.created_at {
+            parts.push(format!("Created {}", discord_timestamp(created_at)));
+        }
+        parts.push(match self.last_fired_at {
+            Some(last) => format!(
+                "Fired {} times, last {}",
+                self.hit_count,
+                discord_timestamp(last)
+            ),
+            None => format!("Fired {} times", self.hit_count),
+        });
+        if self.failure_count > 0 {
+            parts.push(format!(
+                "Emoji refused {} times in a row",
+                self.failure_count
+            ));
+        }
+        parts.join("\n")
+    }
+
+    /// Human readable time until the rule expires, relative to `now`.
+    pub fn time_remaining(&self, now: SystemTime) -> String {
+        match self.expiration {
+            Some(expiration) => {
+                format_duration(expiration.duration_since(now).unwrap_or(Duration::ZERO))
+            }
+            None => "never".to_string(),
+        }
+    }
+}
+
+/// Why a rule was moved to the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveReason {
+    Expired,
+    Removed,
+    /// The archived copy is the rule as it was before an edit.
+    Edited,
+}
+
+impl ArchiveReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArchiveReason::Expired => "expired",
+            ArchiveReason::Removed => "removed",
+            ArchiveReason::Edited => "edited",
+        }
+    }
+}
+
+impl fmt::Display for ArchiveReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for ArchiveReason {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "expired" => Ok(ArchiveReason::Expired),
+            "removed" => Ok(ArchiveReason::Removed),
+            "edited" => Ok(ArchiveReason::Edited),
+            _ => Err(format!("Unknown archive reason {}", s)),
+        }
+    }
+}
+
+/// A rule that has expired, been removed, or been replaced by an edit.
+#[derive(Debug, Clone)]
+pub struct ArchivedAction {
+    pub archive_id: i64,
+    /// The rule as it was when archived, including its original ID.
+    pub action: Action,
+    pub reason: ArchiveReason,
+    pub archived_by: Option<i64>,
+    pub archived_at: SystemTime,
+}
+
+impl ArchivedAction {
+    /// What happened to the rule, e.g. "Removed by @1234 3 hours ago".
+    pub fn describe(&self) -> String {
+        let mut reason = self.reason.as_str().to_string();
+        if let Some(first) = reason.get_mut(0..1) {
+            first.make_ascii_uppercase();
+        }
+        match self.archived_by {
+            Some(uid) => format!(
+                "{} by <@{}> {}",
+                reason,
+                uid,
+                discord_timestamp(self.archived_at)
+            ),
+            None => format!("{} {}", reason, discord_timestamp(self.archived_at)),
+        }
+    }
+}
+
+/// Discord markup showing a time relative to the reader, e.g. "3 hours ago".
+pub fn discord_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    format!("<t:{}:R>", secs)
+}
+
+/// Format a duration as days, hours, minutes and seconds, skipping the empty units.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let units = [
+        (secs / 86400, "d"),
+        (secs % 86400 / 3600, "h"),
+        (secs % 3600 / 60, "m"),
+        (secs % 60, "s"),
+    ];
+    let parts: Vec<String> = units
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{}{}", n, unit))
+        .collect();
+
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(user_id: Option<i64>, regex: Option<&str>) -> Action {
+        Action {
+            id: 1,
+            guild_id: 2,
+            user_id,
+            regex: regex.map(|r| r.to_string()),
+            reactions: vec!["🍞".to_string()],
+            expiration: None,
+            enabled: true,
+            created_by: None,
+            created_at: None,
+            hit_count: 0,
+            last_fired_at: None,
+            failure_count: 0,
+        }
+    }
+
+    #[test]
+    fn summary() {
+        assert_eq!(action(Some(3), Some("bread")).summary(), "@3 /bread/ 🍞");
+        assert_eq!(action(None, Some("bread")).summary(), "anyone /bread/ 🍞");
+        assert_eq!(action(Some(3), None).summary(), "@3 🍞");
+
+        let mut paused = action(None, Some("bread"));
+        paused.enabled = false;
+        assert_eq!(paused.summary(), "anyone /bread/ 🍞 (paused)");
+    }
+
+    #[test]
+    fn format_zero() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+    }
+
+    #[test]
+    fn format_skips_empty_units() {
+        assert_eq!(format_duration(Duration::from_secs(90061)), "1d 1h 1m 1s");
+        assert_eq!(format_duration(Duration::from_secs(3600 + 5)), "1h 5s");
+    }
 }
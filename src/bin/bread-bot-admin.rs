@@ -0,0 +1,567 @@
+use anyhow::{anyhow, Context, Result};
+use bread_bot::action::{format_duration, Action, ArchivedAction};
+use bread_bot::config::Config;
+use bread_bot::emoji::{parse_emotes, spell_out};
+use bread_bot::export::{Format, ImportOptions, RuleSet};
+use bread_bot::store::{self, RuleStore};
+use bread_bot::target::TargetBuilder;
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use serenity::model::prelude::*;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Manage reaction rules without going through discord.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Config file, instead of $BREAD_BOT_CONFIG or /etc/bread-bot.toml
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Print the results as JSON
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Add a new rule
+    Add {
+        /// GuildID of the server to target
+        #[arg(short, long)]
+        guild: u64,
+
+        /// User to target
+        #[arg(short, long)]
+        user: Option<u64>,
+
+        /// The emotes to apply to messages
+        #[arg(short, long)]
+        emotes: String,
+
+        /// A time, in minutes, after which the rule will be removed
+        #[arg(long)]
+        expiration: Option<u64>,
+
+        /// The regex to match against
+        #[arg(short, long)]
+        regex: Option<String>,
+
+        /// Accept a regex that matches every message
+        #[arg(long)]
+        allow_empty_match: bool,
+    },
+
+    /// List the active rules in a server
+    List {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+    },
+
+    /// Show a single rule, along with who created it and how often it fired
+    Show {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+
+        /// ID of the rule
+        id: i64,
+    },
+
+    /// Remove a rule, moving it to the history
+    Remove {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+
+        /// ID of the rule
+        id: i64,
+    },
+
+    /// Change an existing rule, leaving out options to keep them as they are
+    Edit {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+
+        /// ID of the rule
+        id: i64,
+
+        /// User to target
+        #[arg(short, long)]
+        user: Option<u64>,
+
+        /// The emotes to apply to messages
+        #[arg(short, long)]
+        emotes: Option<String>,
+
+        /// A time, in minutes from now, after which the rule will be removed
+        #[arg(long)]
+        expiration: Option<u64>,
+
+        /// The regex to match against
+        #[arg(short, long)]
+        regex: Option<String>,
+
+        /// Accept a regex that matches every message
+        #[arg(long)]
+        allow_empty_match: bool,
+
+        /// Target everyone instead of a single user
+        #[arg(long, conflicts_with = "user")]
+        clear_user: bool,
+
+        /// Match every message instead of a regex
+        #[arg(long, conflicts_with = "regex")]
+        clear_regex: bool,
+    },
+
+    /// Make a rule expire now, or after some minutes
+    Expire {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+
+        /// ID of the rule
+        id: i64,
+
+        /// Minutes from now
+        #[arg(long, default_value_t = 0)]
+        after: u64,
+    },
+
+    /// Remove every rule in a server, every rule targeting a user, or both
+    Purge {
+        /// GuildID of the server
+        #[arg(short, long, required_unless_present = "user")]
+        guild: Option<u64>,
+
+        /// User targeted by the rules
+        #[arg(short, long)]
+        user: Option<u64>,
+    },
+
+    /// List expired, removed and edited rules, most recent first
+    History {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+
+        /// Number of the most recent archived rules to skip
+        #[arg(long, default_value_t = 0)]
+        offset: usize,
+
+        /// Show at most this many archived rules
+        #[arg(long)]
+        limit: Option<usize>,
+    },
+
+    /// Add a rule from the history back as a new rule
+    Restore {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+
+        /// Archive ID of the rule
+        id: i64,
+
+        /// A time, in minutes, after which the restored rule will be removed.
+        /// Defaults to the rule's original expiration
+        #[arg(long)]
+        expiration: Option<u64>,
+    },
+
+    /// Write every active rule in a server to stdout or a file
+    Export {
+        /// GuildID of the server
+        #[arg(short, long)]
+        guild: u64,
+
+        /// toml or json. Defaults to the output file's extension, or toml
+        #[arg(short, long)]
+        format: Option<Format>,
+
+        /// File to write instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Add the rules from an export file
+    Import {
+        /// The exported rules
+        file: PathBuf,
+
+        /// toml or json. Defaults to the file's extension
+        #[arg(short, long)]
+        format: Option<Format>,
+
+        /// GuildID to import into, instead of the one the rules came from
+        #[arg(short, long)]
+        guild: Option<u64>,
+
+        /// Leave out rules that have already expired
+        #[arg(long)]
+        skip_expired: bool,
+
+        /// Accept rules whose regex matches every message
+        #[arg(long)]
+        allow_empty_match: bool,
+    },
+}
+
+/// A rule as printed by `--json`. Times are seconds since the unix epoch.
+#[derive(Serialize)]
+struct RuleJson {
+    id: i64,
+    guild: i64,
+    user: Option<i64>,
+    regex: Option<String>,
+    reactions: Vec<String>,
+    expiration: Option<u64>,
+    enabled: bool,
+    created_by: Option<i64>,
+    created_at: Option<u64>,
+    hit_count: i64,
+    last_fired_at: Option<u64>,
+    failure_count: i64,
+}
+
+/// A rule from the history as printed by `--json`.
+#[derive(Serialize)]
+struct ArchivedJson {
+    archive_id: i64,
+    reason: String,
+    archived_by: Option<i64>,
+    archived_at: u64,
+    rule: RuleJson,
+}
+
+impl From<&ArchivedAction> for ArchivedJson {
+    fn from(archived: &ArchivedAction) -> Self {
+        ArchivedJson {
+            archive_id: archived.archive_id,
+            reason: archived.reason.to_string(),
+            archived_by: archived.archived_by,
+            archived_at: unix(archived.archived_at),
+            rule: RuleJson::from(&archived.action),
+        }
+    }
+}
+
+fn unix(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+impl From<&Action> for RuleJson {
+    fn from(action: &Action) -> Self {
+        RuleJson {
+            id: action.id,
+            guild: action.guild_id,
+            user: action.user_id,
+            regex: action.regex.clone(),
+            reactions: action.reactions.clone(),
+            expiration: action.expiration.map(unix),
+            enabled: action.enabled,
+            created_by: action.created_by,
+            created_at: action.created_at.map(unix),
+            hit_count: action.hit_count,
+            last_fired_at: action.last_fired_at.map(unix),
+            failure_count: action.failure_count,
+        }
+    }
+}
+
+/// Print a value as JSON, or as the given text.
+fn output<T: Serialize>(json: bool, value: &T, text: impl FnOnce() -> String) -> Result<()> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value)?);
+    } else {
+        println!("{}", text());
+    }
+    Ok(())
+}
+
+fn describe(action: &Action, now: SystemTime) -> String {
+    format!(
+        "Rule {} in guild {}\nUser: {}\nRegex: {}\nReactions: {}\nExpires in: {}\n\
+        Enabled: {}\nCreated by: {}\nFired: {} times",
+        action.id,
+        action.guild_id,
+        action
+            .user_id
+            .map(|u| u.to_string())
+            .unwrap_or_else(|| "anyone".to_string()),
+        action.regex.as_deref().unwrap_or("none"),
+        action.reactions.join(" "),
+        action.time_remaining(now),
+        action.enabled,
+        action
+            .created_by
+            .map(|u| u.to_string())
+            .unwrap_or_else(|| "unknown".to_string()),
+        action.hit_count,
+    )
+}
+
+fn find(store: &dyn RuleStore, gid: GuildId, id: i64) -> Result<Action> {
+    store
+        .get(gid, id)?
+        .ok_or_else(|| anyhow!("No rule {} in guild {}", id, gid))
+}
+
+/// Members of the guild that rules can't single out.
+fn immune_users(store: &dyn RuleStore, gid: GuildId) -> Result<Vec<UserId>> {
+    Ok(store
+        .immune_users(gid)?
+        .iter()
+        .map(|i| UserId::new(i.user_id as u64))
+        .collect())
+}
+
+/// Plain words are spelled out in regional indicator letters, anything else
+/// is read as emoji, including guild emoji such as `<:bread:1234>`.
+fn emotes_arg(emotes: &str) -> Result<String> {
+    if let Some(spelled) = spell_out(emotes) {
+        let letters: HashSet<char> = emotes.to_ascii_lowercase().chars().collect();
+        if letters.len() != emotes.len() {
+            return Err(anyhow!("Input ascii had duplicate characters"));
+        }
+        return Ok(spelled);
+    }
+    parse_emotes(emotes)?;
+    Ok(emotes.to_string())
+}
+
+/// The format named on the command line, or the one matching the file name.
+fn pick_format(format: Option<Format>, file: Option<&PathBuf>) -> Option<Format> {
+    format.or_else(|| {
+        file.and_then(|f| f.file_name())
+            .and_then(|f| Format::from_file_name(&f.to_string_lossy()))
+    })
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+
+    let config_data = Config::load(&Config::path(args.config))?;
+    let store = store::connect(&config_data.database_url, 1)
+        .with_context(|| format!("Error connecting to {}", config_data.database_url))?;
+    let store = &*store;
+
+    let now = SystemTime::now();
+    let json = args.json;
+    match args.command {
+        Command::Add {
+            guild,
+            user,
+            emotes,
+            expiration,
+            regex,
+            allow_empty_match,
+        } => {
+            let mut builder = TargetBuilder::default()
+                .allow_empty_match(allow_empty_match)
+                .set_emotes(&emotes_arg(&emotes)?)
+                .set_guild(GuildId::from(guild))
+                .set_immune(&immune_users(store, GuildId::from(guild))?);
+            if let Some(u) = user {
+                builder = builder.set_user(UserId::from(u));
+            }
+            if let Some(e) = expiration {
+                builder = builder.set_expiration(e);
+            }
+            if let Some(r) = regex {
+                builder = builder.set_regex(&r);
+            }
+            let target = builder.build()?;
+
+            let id = store.insert(&target, now)?;
+            output(json, &serde_json::json!({ "id": id }), || {
+                format!("Added rule {}", id)
+            })?;
+        }
+        Command::List { guild } => {
+            let rules = store.list(GuildId::from(guild), now)?;
+            let list: Vec<RuleJson> = rules.iter().map(RuleJson::from).collect();
+            output(json, &list, || {
+                rules
+                    .iter()
+                    .map(|a| format!("{}\t{}\t{}", a.id, a.time_remaining(now), a.summary()))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })?;
+        }
+        Command::Show { guild, id } => {
+            let action = find(store, GuildId::from(guild), id)?;
+            output(json, &RuleJson::from(&action), || describe(&action, now))?;
+        }
+        Command::Remove { guild, id } => {
+            if !store.remove(GuildId::from(guild), id, None, now)? {
+                return Err(anyhow!("No rule {} in guild {}", id, guild));
+            }
+            output(json, &serde_json::json!({ "removed": id }), || {
+                format!("Removed rule {}", id)
+            })?;
+        }
+        Command::Edit {
+            guild,
+            id,
+            user,
+            emotes,
+            expiration,
+            regex,
+            allow_empty_match,
+            clear_user,
+            clear_regex,
+        } => {
+            let action = find(store, GuildId::from(guild), id)?;
+            let mut builder = TargetBuilder::from_action(&action)
+                .allow_empty_match(allow_empty_match)
+                .set_immune(&immune_users(store, GuildId::from(guild))?);
+            if let Some(u) = user {
+                builder = builder.set_user(UserId::from(u));
+            }
+            if let Some(e) = emotes {
+                builder = builder.set_emotes(&emotes_arg(&e)?);
+            }
+            if let Some(e) = expiration {
+                builder = builder.set_expiration(e);
+            }
+            if let Some(r) = regex {
+                builder = builder.set_regex(&r);
+            }
+            if clear_user {
+                builder = builder.clear_user();
+            }
+            if clear_regex {
+                builder = builder.clear_regex();
+            }
+            let target = builder.build()?;
+
+            if !store.update(id, &target, None, now)? {
+                return Err(anyhow!("No rule {} in guild {}", id, guild));
+            }
+            let action = find(store, GuildId::from(guild), id)?;
+            output(json, &RuleJson::from(&action), || {
+                format!("Updated rule {}: {}", id, action.summary())
+            })?;
+        }
+        Command::Expire { guild, id, after } => {
+            let expires = now + Duration::from_secs(after * 60);
+            if !store.set_expiration(GuildId::from(guild), id, Some(expires))? {
+                return Err(anyhow!("No rule {} in guild {}", id, guild));
+            }
+            output(
+                json,
+                &serde_json::json!({ "id": id, "expiration": unix(expires) }),
+                || match after {
+                    0 => format!("Rule {} expired", id),
+                    _ => format!(
+                        "Rule {} expires in {}",
+                        id,
+                        format_duration(Duration::from_secs(after * 60))
+                    ),
+                },
+            )?;
+        }
+        Command::Purge { guild, user } => {
+            let removed =
+                store.purge(guild.map(GuildId::from), user.map(UserId::from), None, now)?;
+            let list: Vec<RuleJson> = removed.iter().map(RuleJson::from).collect();
+            output(json, &list, || format!("Removed {} rules", removed.len()))?;
+        }
+        Command::History {
+            guild,
+            offset,
+            limit,
+        } => {
+            let history =
+                store.history(GuildId::from(guild), offset, limit.unwrap_or(usize::MAX))?;
+            let list: Vec<ArchivedJson> = history.iter().map(ArchivedJson::from).collect();
+            output(json, &list, || {
+                history
+                    .iter()
+                    .map(|a| {
+                        format!(
+                            "{}\t#{}\t{}\t{}",
+                            a.archive_id,
+                            a.action.id,
+                            a.reason,
+                            a.action.summary()
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })?;
+        }
+        Command::Restore {
+            guild,
+            id,
+            expiration,
+        } => {
+            let archived = store
+                .get_archived(GuildId::from(guild), id)?
+                .ok_or_else(|| anyhow!("No archived rule {} in guild {}", id, guild))?;
+            let mut builder = TargetBuilder::from_action(&archived.action)
+                .set_immune(&immune_users(store, GuildId::from(guild))?);
+            if let Some(e) = expiration {
+                builder = builder.set_expiration(e);
+            }
+            let target = builder.build()?;
+
+            let new_id = store.insert(&target, now)?;
+            output(
+                json,
+                &serde_json::json!({ "id": new_id, "restored": id }),
+                || format!("Restored as rule {}", new_id),
+            )?;
+        }
+        Command::Export {
+            guild,
+            format,
+            output,
+        } => {
+            let format = pick_format(format, output.as_ref()).unwrap_or(Format::Toml);
+            let gid = GuildId::from(guild);
+            let actions = store.list(gid, now)?;
+            let text = RuleSet::from_actions(gid, &actions).write(format)?;
+            match output {
+                Some(path) => std::fs::write(&path, text)
+                    .with_context(|| format!("Error writing {}", path.display()))?,
+                None => print!("{}", text),
+            }
+        }
+        Command::Import {
+            file,
+            format,
+            guild,
+            skip_expired,
+            allow_empty_match,
+        } => {
+            let format = pick_format(format, Some(&file))
+                .ok_or_else(|| anyhow!("Can't tell the format of {}", file.display()))?;
+            let text = std::fs::read_to_string(&file)
+                .with_context(|| format!("Error reading {}", file.display()))?;
+            let options = ImportOptions {
+                guild: guild.map(GuildId::from),
+                skip_expired,
+                allow_empty_match,
+                creator: None,
+            };
+            let ids = RuleSet::parse(&text, format)?.import(store, options, now)?;
+            output(json, &serde_json::json!({ "ids": ids }), || {
+                format!("Imported {} rules", ids.len())
+            })?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use anyhow::{Context, Result};
+use bread_bot::config::Config;
+use bread_bot::evaluation::CompiledRules;
+use bread_bot::store;
+use clap::Parser;
+use serenity::model::id::{GuildId, UserId};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Show which rules would fire for a message, without reacting to anything.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Config file, instead of $BREAD_BOT_CONFIG or /etc/bread-bot.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// GuildID of the server the message is sent in
+    #[arg(short, long)]
+    guild: u64,
+
+    /// User sending the message
+    #[arg(short, long)]
+    user: u64,
+
+    /// Letters previously typed one message at a time by the user, to check
+    /// against the regex rules as well
+    #[arg(short, long)]
+    column: Option<String>,
+
+    /// The message content
+    text: String,
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
+
+    let config_data = Config::load(&Config::path(args.config))?;
+    let store = store::connect(&config_data.database_url, 1)
+        .with_context(|| format!("Error connecting to {}", config_data.database_url))?;
+
+    let (gid, uid, now) = (
+        GuildId::new(args.guild),
+        UserId::new(args.user),
+        SystemTime::now(),
+    );
+    let immune = store.immune_users(gid)?;
+    let rules = CompiledRules::new(store.active_for_message(gid, uid, now)?)
+        .with_immune(immune.iter().map(|i| UserId::new(i.user_id as u64)));
+    println!(
+        "{}",
+        rules
+            .evaluate(uid, &args.text, args.column.as_deref(), now)
+            .report()
+    );
+
+    Ok(())
+}
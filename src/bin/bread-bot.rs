@@ -1,42 +1,106 @@
-extern crate diesel;
+use anyhow::{anyhow, Context, Result};
 use bread_bot::config::Config;
 use bread_bot::handler::Handler;
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
+use bread_bot::settings::Settings;
+use bread_bot::shutdown;
+use bread_bot::store::{self, StoreError};
+use bread_bot::sweeper::Sweeper;
+use bread_bot::systemd;
+use clap::Parser;
+use serenity::model::id::{ChannelId, GuildId};
 use serenity::prelude::*;
-use std::fs::File;
-use std::io::{BufReader, Read};
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Config file, instead of $BREAD_BOT_CONFIG or /etc/bread-bot.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Apply any pending database migrations and exit
+    #[arg(long)]
+    migrate_only: bool,
+}
+
+/// How long to let reactions that are under way finish when shutting down.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
 
 #[tokio::main]
-async fn main() {
-    // Read in config file
-    let mut reader = BufReader::new(
-        File::open("/etc/bread-bot.toml")
-            .expect("Expected /etc/bread-bot.toml to exist and be readable"),
-    );
+async fn main() -> Result<(), anyhow::Error> {
+    let args = Args::parse();
 
-    // Parse config file
-    let mut config_data = String::new();
-    reader
-        .read_to_string(&mut config_data)
-        .expect("Expected valid UTF-8 in config file");
+    let config_data = Config::load(&Config::path(args.config))?;
+    let store = store::connect(&config_data.database_url, config_data.pool_size)
+        .with_context(|| format!("Error connecting to {}", config_data.database_url))?;
 
-    let config_data: Config = toml::from_str(&config_data).expect("Invalid config file format");
-    let connection = PgConnection::establish(&config_data.postgres_url)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", config_data.postgres_url));
+    // Bring the schema up to date before touching any rules
+    match store::blocking(&store, |s| s.migrate()).await {
+        Ok(applied) => {
+            for version in applied {
+                println!("Applied migration {}", version);
+            }
+        }
+        Err(e @ StoreError::SchemaTooNew(_)) => return Err(anyhow!("Refusing to start: {}", e)),
+        Err(e) => return Err(anyhow!("Error running migrations: {}", e)),
+    }
+    if args.migrate_only {
+        return Ok(());
+    }
 
-    let mut client = Client::builder(
-        &config_data.discord_token,
-        GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT,
+    let mut intents = GatewayIntents::GUILD_MESSAGES | GatewayIntents::MESSAGE_CONTENT;
+    if config_data.dm_commands {
+        intents |= GatewayIntents::DIRECT_MESSAGES;
+    }
+    let handler = Handler::new(
+        store.clone(),
+        Duration::from_secs(config_data.cache_refresh),
     )
-    .application_id(config_data.application_id.into())
-    .event_handler(Handler::new(Arc::new(Mutex::new(connection))))
-    .await
-    .expect("Err creating client");
+    .dm_commands(config_data.dm_commands)
+    .settings(Settings::new(config_data.guild.clone()));
+    let in_flight = handler.in_flight();
+    let mut client = Client::builder(config_data.discord_token()?, intents)
+        .application_id(config_data.application_id.into())
+        .event_handler(handler)
+        .await
+        .context("Error creating discord client")?;
+
+    // Clean up expired rules in the background
+    let announce = config_data
+        .expiry_announcement
+        .iter()
+        .map(|a| (GuildId::new(a.guild), ChannelId::new(a.channel)))
+        .collect();
+    let sweeper = Sweeper::new(
+        store,
+        Duration::from_secs(config_data.sweep_interval),
+        announce,
+    );
+    tokio::spawn(sweeper.run(client.http.clone()));
+
+    // Disconnect cleanly on SIGTERM or SIGINT, which makes start() return
+    let shard_manager = client.shard_manager.clone();
+    tokio::spawn(async move {
+        if let Err(e) = shutdown::signal_received().await {
+            println!("Can't listen for shutdown signals: {}", e);
+            return;
+        }
+        println!("Shutting down");
+        systemd::notify_stopping();
+        shard_manager.shutdown_all().await;
+    });
+    tokio::spawn(systemd::watchdog(client.shard_manager.clone()));
 
     if let Err(why) = client.start().await {
         println!("Client error: {:?}", why);
     }
+
+    if !in_flight.wait(SHUTDOWN_GRACE).await {
+        println!(
+            "Gave up waiting on {} messages still being handled",
+            in_flight.count()
+        );
+    }
+    Ok(())
 }
@@ -1,8 +1,375 @@
+use crate::settings::GuildSettings;
 use serde::Deserialize;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the config is read from when neither `--config` nor `BREAD_BOT_CONFIG`
+/// is given.
+pub const DEFAULT_PATH: &str = "/etc/bread-bot.toml";
+
+/// Environment variable naming the config file.
+pub const PATH_VAR: &str = "BREAD_BOT_CONFIG";
+
+/// Environment variables starting with this override the config field of the
+/// same name, e.g. `BREAD_BOT_DATABASE_URL`.
+const ENV_PREFIX: &str = "BREAD_BOT_";
+
+/// How the value of an environment override is read.
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Number,
+    Bool,
+    /// An inline TOML value, e.g. `[{ guild = 1, channel = 2 }]`.
+    Toml,
+}
+
+/// Fields that can be overridden from the environment.
+const OVERRIDES: &[(&str, Kind)] = &[
+    ("discord_token", Kind::Text),
+    ("discord_token_file", Kind::Text),
+    ("database_url", Kind::Text),
+    ("pool_size", Kind::Number),
+    ("cache_refresh", Kind::Number),
+    ("sweep_interval", Kind::Number),
+    ("dm_commands", Kind::Bool),
+    ("guild", Kind::Toml),
+    ("expiry_announcement", Kind::Toml),
+    ("application_id", Kind::Number),
+];
+
+/// Name of the token in systemd's credentials directory.
+const TOKEN_CREDENTIAL: &str = "discord_token";
 
 #[derive(Deserialize)]
 pub struct Config {
-    pub discord_token: String,
-    pub postgres_url: String,
+    /// Read through `discord_token()`, which also looks at the token file.
+    discord_token: Option<String>,
+    /// File holding the token, such as a systemd credential.
+    discord_token_file: Option<PathBuf>,
+    /// Where the rules are stored. The scheme picks the backend, see
+    /// `store::connect`.
+    #[serde(alias = "postgres_url")]
+    pub database_url: String,
+    /// Maximum number of database connections the bot keeps open.
+    #[serde(default = "default_pool_size")]
+    pub pool_size: u32,
+    /// How often, in seconds, cached rules are reloaded to pick up changes
+    /// made outside the bot.
+    #[serde(default = "default_cache_refresh")]
+    pub cache_refresh: u64,
+    /// How often, in seconds, expired rules are deleted. Can't be 0.
+    #[serde(default = "default_sweep_interval")]
+    pub sweep_interval: u64,
+    /// Answer direct messages with the rules targeting the sender. Otherwise
+    /// direct messages are ignored.
+    #[serde(default)]
+    pub dm_commands: bool,
+    /// Per-guild settings, from `[[guild]]` entries.
+    #[serde(default)]
+    pub guild: Vec<GuildSettings>,
+    /// Channels to announce expired rules in.
+    #[serde(default)]
+    pub expiry_announcement: Vec<Announcement>,
     pub application_id: u64,
 }
+
+#[derive(Deserialize)]
+pub struct Announcement {
+    pub guild: u64,
+    pub channel: u64,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// An environment override that doesn't fit its field, along with what it
+    /// should be.
+    BadOverride(String, String, &'static str),
+    /// A field that has to be more than 0.
+    Zero(&'static str),
+    MissingToken,
+    TokenFile(PathBuf, io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            ConfigError::Read(path, e) => {
+                write!(f, "Couldn't read config file {}: {}", path.display(), e)
+            }
+            ConfigError::Parse(path, e) => {
+                write!(f, "Invalid config file {}: {}", path.display(), e)
+            }
+            ConfigError::BadOverride(var, value, expected) => {
+                write!(f, "{} should be {}, not {:?}", var, expected, value)
+            }
+            ConfigError::Zero(field) => write!(f, "{} has to be more than 0", field),
+            ConfigError::MissingToken => write!(
+                f,
+                "No discord token, set discord_token or discord_token_file in the config, \
+                or {}DISCORD_TOKEN",
+                ENV_PREFIX
+            ),
+            ConfigError::TokenFile(path, e) => {
+                write!(f, "Couldn't read token file {}: {}", path.display(), e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// The config file to use: `flag` if given, then `BREAD_BOT_CONFIG`, then
+    /// `/etc/bread-bot.toml`.
+    pub fn path(flag: Option<PathBuf>) -> PathBuf {
+        flag.or_else(|| env::var_os(PATH_VAR).map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_PATH))
+    }
+
+    /// Read the config file at `path`, applying any overrides from the
+    /// environment.
+    pub fn load(path: &Path) -> Result<Config, ConfigError> {
+        let text =
+            fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+        Config::parse(path, &text, utf8_vars(env::vars_os())?)
+    }
+
+    fn parse(
+        path: &Path,
+        text: &str,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config, ConfigError> {
+        let parse_error = |e| ConfigError::Parse(path.to_path_buf(), e);
+        let mut table: toml::Table = toml::from_str(text).map_err(parse_error)?;
+        let (mut env_token, mut token_file) = (false, false);
+        for (var, value) in vars {
+            let Some(key) = var.strip_prefix(ENV_PREFIX).map(str::to_ascii_lowercase) else {
+                continue;
+            };
+            let Some((key, kind)) = OVERRIDES.iter().find(|(k, _)| *k == key) else {
+                continue;
+            };
+            let parsed = match kind {
+                Kind::Text => Some(toml::Value::String(value.clone())),
+                Kind::Number => value.parse().ok().map(toml::Value::Integer),
+                Kind::Bool => value.parse().ok().map(toml::Value::Boolean),
+                Kind::Toml => toml::from_str::<toml::Table>(&format!("value = {}", value))
+                    .ok()
+                    .and_then(|mut t| t.remove("value")),
+            };
+            let Some(parsed) = parsed else {
+                let expected = match kind {
+                    Kind::Number => "a number",
+                    Kind::Bool => "true or false",
+                    _ => "an inline TOML value",
+                };
+                return Err(ConfigError::BadOverride(var, value, expected));
+            };
+            match *key {
+                "database_url" => {
+                    table.remove("postgres_url");
+                }
+                // Whichever form the token takes in the environment, it
+                // replaces the one in the file
+                "discord_token" => {
+                    env_token = true;
+                    table.remove("discord_token_file");
+                }
+                "discord_token_file" => {
+                    token_file = true;
+                }
+                _ => (),
+            }
+            table.insert(key.to_string(), parsed);
+        }
+        if token_file && !env_token {
+            table.remove("discord_token");
+        }
+        let config: Config = toml::Value::Table(table).try_into().map_err(parse_error)?;
+        if config.pool_size == 0 {
+            return Err(ConfigError::Zero("pool_size"));
+        }
+        if config.sweep_interval == 0 {
+            return Err(ConfigError::Zero("sweep_interval"));
+        }
+        Ok(config)
+    }
+
+    /// The bot token, from the config or the environment, the token file, or
+    /// the systemd credential named `discord_token`, in that order.
+    pub fn discord_token(&self) -> Result<String, ConfigError> {
+        if let Some(token) = &self.discord_token {
+            return Ok(token.clone());
+        }
+        let path = match &self.discord_token_file {
+            Some(path) => path.clone(),
+            None => match env::var_os("CREDENTIALS_DIRECTORY") {
+                Some(dir) => Path::new(&dir).join(TOKEN_CREDENTIAL),
+                None => return Err(ConfigError::MissingToken),
+            },
+        };
+        match fs::read_to_string(&path) {
+            Ok(token) => Ok(token.trim().to_string()),
+            Err(e) => Err(ConfigError::TokenFile(path, e)),
+        }
+    }
+}
+
+/// Keep the environment variables that are valid UTF-8. Others are skipped,
+/// unless they look like an override, since those can't be read.
+fn utf8_vars(
+    vars: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut kept = Vec::new();
+    for (var, value) in vars {
+        match (var.into_string(), value.into_string()) {
+            (Ok(var), Ok(value)) => kept.push((var, value)),
+            (var, value) => {
+                let var = var.unwrap_or_else(|v| v.to_string_lossy().into_owned());
+                if var.starts_with(ENV_PREFIX) {
+                    let value = value.unwrap_or_else(|v| v.to_string_lossy().into_owned());
+                    return Err(ConfigError::BadOverride(var, value, "valid UTF-8"));
+                }
+            }
+        }
+    }
+    Ok(kept)
+}
+
+fn default_pool_size() -> u32 {
+    4
+}
+
+fn default_cache_refresh() -> u64 {
+    60
+}
+
+fn default_sweep_interval() -> u64 {
+    60
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn environment_overrides() {
+        let text = r#"
+            postgres_url = "postgres://localhost/bread"
+            application_id = 1
+        "#;
+        let path = Path::new("test.toml");
+        let config = Config::parse(
+            path,
+            text,
+            vars(&[
+                ("BREAD_BOT_DATABASE_URL", "memory:"),
+                ("BREAD_BOT_POOL_SIZE", "2"),
+                ("BREAD_BOT_DISCORD_TOKEN", "secret"),
+                ("BREAD_BOT_DM_COMMANDS", "true"),
+                ("BREAD_BOT_GUILD", "[{ id = 3, ignore_bots = false }]"),
+                (
+                    "BREAD_BOT_EXPIRY_ANNOUNCEMENT",
+                    "[{ guild = 3, channel = 4 }]",
+                ),
+                ("BREAD_BOT_SETTINGS", "ignored"),
+                ("HOME", "/root"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.database_url, "memory:");
+        assert_eq!(config.pool_size, 2);
+        assert_eq!(config.cache_refresh, 60);
+        assert_eq!(config.discord_token().unwrap(), "secret");
+        assert!(config.dm_commands);
+        assert_eq!(config.guild[0].id, 3);
+        assert!(!config.guild[0].ignore_bots);
+        assert_eq!(config.expiry_announcement[0].channel, 4);
+
+        assert!(matches!(
+            Config::parse(path, text, vars(&[("BREAD_BOT_POOL_SIZE", "lots")])),
+            Err(ConfigError::BadOverride(var, ..)) if var == "BREAD_BOT_POOL_SIZE"
+        ));
+        assert!(matches!(
+            Config::parse(path, text, vars(&[("BREAD_BOT_DM_COMMANDS", "yes")])),
+            Err(ConfigError::BadOverride(var, ..)) if var == "BREAD_BOT_DM_COMMANDS"
+        ));
+        assert!(matches!(
+            Config::parse(path, "application_id = 1", Vec::new()),
+            Err(ConfigError::Parse(..))
+        ));
+        assert!(matches!(
+            Config::parse(path, text, vars(&[("BREAD_BOT_SWEEP_INTERVAL", "0")])),
+            Err(ConfigError::Zero("sweep_interval"))
+        ));
+        assert!(matches!(
+            Config::parse(path, text, vars(&[("BREAD_BOT_POOL_SIZE", "0")])),
+            Err(ConfigError::Zero("pool_size"))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_environment() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let bad = || OsString::from_vec(vec![b'b', 0xff]);
+        let kept = utf8_vars([
+            (bad(), OsString::from("x")),
+            (OsString::from("LANG"), bad()),
+            (OsString::from("BREAD_BOT_GUILD"), OsString::from("[]")),
+        ])
+        .unwrap();
+        assert_eq!(kept, vars(&[("BREAD_BOT_GUILD", "[]")]));
+
+        assert!(matches!(
+            utf8_vars([(OsString::from("BREAD_BOT_DATABASE_URL"), bad())]),
+            Err(ConfigError::BadOverride(var, ..)) if var == "BREAD_BOT_DATABASE_URL"
+        ));
+    }
+
+    #[test]
+    fn environment_token_beats_file() {
+        let path = Path::new("test.toml");
+        let text = r#"
+            database_url = "memory:"
+            application_id = 1
+            discord_token = "from file"
+        "#;
+        let token_file = env::temp_dir().join(format!("bread-bot-token-{}", std::process::id()));
+        fs::write(&token_file, "from token file\n").unwrap();
+        let file_var = token_file.to_string_lossy().to_string();
+
+        let config = Config::parse(
+            path,
+            text,
+            vars(&[("BREAD_BOT_DISCORD_TOKEN_FILE", &file_var)]),
+        )
+        .unwrap();
+        assert_eq!(config.discord_token().unwrap(), "from token file");
+
+        let text = format!("{}discord_token_file = {:?}\n", text, file_var);
+        let config = Config::parse(
+            path,
+            &text,
+            vars(&[("BREAD_BOT_DISCORD_TOKEN", "from env")]),
+        )
+        .unwrap();
+        assert_eq!(config.discord_token().unwrap(), "from env");
+        fs::remove_file(&token_file).unwrap();
+    }
+}
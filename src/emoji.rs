@@ -0,0 +1,309 @@
+use serenity::model::channel::ReactionType;
+use serenity::model::id::EmojiId;
+use std::fmt;
+use std::str::FromStr;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A single reaction as stored in the `reactions` column. Unicode emoji are
+/// stored as the full grapheme cluster, so flags, skin tones, ZWJ sequences
+/// and keycaps survive intact, and guild emoji are stored in Discord's
+/// `<:name:id>` or `<a:name:id>` format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Emoji {
+    Unicode(String),
+    Custom {
+        animated: bool,
+        name: String,
+        id: u64,
+    },
+}
+
+impl Emoji {
+    pub fn to_reaction_type(&self) -> ReactionType {
+        match self {
+            Emoji::Unicode(s) => ReactionType::Unicode(s.clone()),
+            Emoji::Custom { animated, name, id } => ReactionType::Custom {
+                animated: *animated,
+                id: EmojiId::new(*id),
+                name: Some(name.clone()),
+            },
+        }
+    }
+
+    /// Parse a guild emoji in `<:name:id>` or `<a:name:id>` form.
+    fn parse_custom(s: &str) -> Option<Emoji> {
+        let inner = s.strip_prefix('<')?.strip_suffix('>')?;
+        let mut parts = inner.split(':');
+        let animated = match parts.next()? {
+            "" => false,
+            "a" => true,
+            _ => return None,
+        };
+        let name = parts.next()?;
+        let id = parts.next()?.parse::<u64>().ok()?;
+        if parts.next().is_some()
+            || name.is_empty()
+            || !name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        {
+            return None;
+        }
+
+        Some(Emoji::Custom {
+            animated,
+            name: name.to_string(),
+            id,
+        })
+    }
+}
+
+impl fmt::Display for Emoji {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Emoji::Unicode(s) => write!(f, "{}", s),
+            Emoji::Custom { animated, name, id } => {
+                write!(f, "<{}:{}:{}>", if *animated { "a" } else { "" }, name, id)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct BadEmoji(pub String);
+
+impl fmt::Display for BadEmoji {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Not a valid emoji: {}", self.0)
+    }
+}
+
+impl std::error::Error for BadEmoji {}
+
+impl FromStr for Emoji {
+    type Err = BadEmoji;
+
+    /// Parse a single stored entry.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with('<') {
+            Emoji::parse_custom(s).ok_or_else(|| BadEmoji(s.to_string()))
+        } else if s.graphemes(true).count() == 1 && is_emoji(s) {
+            Ok(Emoji::Unicode(s.to_string()))
+        } else {
+            Err(BadEmoji(s.to_string()))
+        }
+    }
+}
+
+/// Split user input into emoji. Guild emoji are taken whole, everything else
+/// is split by grapheme cluster. Whitespace only separates entries, which
+/// allows regional indicator letters to be spelled out without pairing up
+/// into flags. Duplicates are dropped since Discord only shows them once.
+/// Anything that isn't an emoji, such as plain letters or punctuation, is an
+/// error.
+pub fn parse_emotes(input: &str) -> Result<Vec<Emoji>, BadEmoji> {
+    let mut emotes: Vec<Emoji> = Vec::new();
+    let mut rest = input;
+
+    while !rest.is_empty() {
+        let (emoji, len) = if rest.starts_with('<') {
+            let end = rest.find('>').ok_or_else(|| BadEmoji(rest.to_string()))?;
+            let token = &rest[..=end];
+            let emoji = Emoji::parse_custom(token).ok_or_else(|| BadEmoji(token.to_string()))?;
+            (Some(emoji), token.len())
+        } else {
+            let grapheme = rest.graphemes(true).next().unwrap_or(rest);
+            let emoji = if grapheme
+                .chars()
+                .all(|c| c.is_whitespace() || c == '\u{200b}')
+            {
+                None
+            } else if is_emoji(grapheme) {
+                Some(Emoji::Unicode(grapheme.to_string()))
+            } else {
+                return Err(BadEmoji(grapheme.to_string()));
+            };
+            (emoji, grapheme.len())
+        };
+
+        if let Some(emoji) = emoji {
+            if !emotes.contains(&emoji) {
+                emotes.push(emoji);
+            }
+        }
+        rest = &rest[len..];
+    }
+
+    Ok(emotes)
+}
+
+/// Spell out a plain word in regional indicator letters, separated by spaces
+/// so that pairs of them aren't read as flags. Returns `None` unless the word
+/// is only ascii letters.
+pub fn spell_out(word: &str) -> Option<String> {
+    if word.is_empty() || !word.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    let letters: Vec<String> = word
+        .to_ascii_lowercase()
+        .bytes()
+        .filter_map(|b| char::from_u32(0x1f1e6 + u32::from(b - b'a')))
+        .map(String::from)
+        .collect();
+    Some(letters.join(" "))
+}
+
+/// Put back together the reactions of a rule stored before reactions were
+/// structured, when each entry held a single codepoint. Skin tones, ZWJ
+/// sequences and keycaps are rejoined by grapheme cluster. Regional indicators
+/// were used to spell out words, so they are only paired into a flag when the
+/// rule's reactions are nothing but that flag. Rows with longer entries were
+/// written since, and are returned as they are.
+pub fn regroup_legacy(reactions: &[String]) -> Vec<String> {
+    if reactions.iter().any(|r| r.chars().count() > 1) {
+        return reactions.to_vec();
+    }
+    let joined = reactions.concat();
+    if joined.graphemes(true).count() == 1 {
+        return vec![joined];
+    }
+    let mut regrouped = Vec::new();
+    for grapheme in joined.graphemes(true) {
+        if grapheme.chars().all(is_regional_indicator) {
+            regrouped.extend(grapheme.chars().map(|c| c.to_string()));
+        } else {
+            regrouped.push(grapheme.to_string());
+        }
+    }
+    regrouped
+}
+
+/// Whether a grapheme cluster is something Discord can react with: it has to
+/// hold a pictograph or symbol, or be a keycap such as 1️⃣, and can't carry
+/// whitespace along with it.
+fn is_emoji(grapheme: &str) -> bool {
+    if grapheme.chars().any(char::is_whitespace) {
+        return false;
+    }
+    let mut chars = grapheme.chars();
+    let keycap = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_digit() || c == '#' || c == '*')
+        && chars.any(|c| c == '\u{20e3}');
+    keycap || grapheme.chars().any(is_pictograph)
+}
+
+/// Codepoints that are drawn as emoji, from the blocks Unicode assigns them.
+fn is_pictograph(c: char) -> bool {
+    matches!(
+        c,
+        '\u{a9}'
+            | '\u{ae}'
+            | '\u{203c}'
+            | '\u{2049}'
+            | '\u{2122}'
+            | '\u{2139}'
+            | '\u{2194}'..='\u{21aa}'
+            | '\u{231a}'..='\u{23ff}'
+            | '\u{24c2}'
+            | '\u{25aa}'..='\u{27bf}'
+            | '\u{2934}'
+            | '\u{2935}'
+            | '\u{2b05}'..='\u{2b55}'
+            | '\u{3030}'
+            | '\u{303d}'
+            | '\u{3297}'
+            | '\u{3299}'
+            | '\u{1f000}'..='\u{1faff}'
+    )
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1f1e6}'..='\u{1f1ff}').contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Vec<String> {
+        parse_emotes(input)
+            .unwrap()
+            .iter()
+            .map(|e| e.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn multi_codepoint() {
+        // Flag, skin tone, ZWJ family and keycap
+        assert_eq!(parse("🇺🇸👍🏽👨‍👩‍👧1️⃣"), vec!["🇺🇸", "👍🏽", "👨‍👩‍👧", "1️⃣"]);
+    }
+
+    #[test]
+    fn whitespace_separates() {
+        assert_eq!(parse("🇧 🇷\u{200b}🇪"), vec!["🇧", "🇷", "🇪"]);
+    }
+
+    #[test]
+    fn custom() {
+        assert_eq!(
+            parse("🍞<:bread:1234><a:toast:5678>"),
+            vec!["🍞", "<:bread:1234>", "<a:toast:5678>"]
+        );
+        assert_eq!(
+            "<:bread:1234>".parse::<Emoji>(),
+            Ok(Emoji::Custom {
+                animated: false,
+                name: "bread".to_string(),
+                id: 1234
+            })
+        );
+    }
+
+    #[test]
+    fn bad_custom() {
+        assert!(parse_emotes("<:bread:>").is_err());
+        assert!(parse_emotes("<:bread:1234").is_err());
+    }
+
+    #[test]
+    fn spelled_words() {
+        assert_eq!(spell_out("Bread").as_deref(), Some("🇧 🇷 🇪 🇦 🇩"));
+        assert_eq!(spell_out("<:bread:1234>"), None);
+        assert_eq!(spell_out("no way"), None);
+        assert_eq!(spell_out(""), None);
+    }
+
+    #[test]
+    fn legacy_rows() {
+        let split = |s: &str| s.chars().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(regroup_legacy(&split("🇺🇸")), ["🇺🇸"]);
+        assert_eq!(regroup_legacy(&split("👍🏽")), ["👍🏽"]);
+        assert_eq!(
+            regroup_legacy(&split("🇧🇷🇪🇦🇩👨‍👩‍👧1️⃣🍞")),
+            ["🇧", "🇷", "🇪", "🇦", "🇩", "👨‍👩‍👧", "1️⃣", "🍞"]
+        );
+        // Rows written since are left alone
+        assert_eq!(
+            regroup_legacy(&parse("🇧 🇷👍🏽<:bread:1234>")),
+            ["🇧", "🇷", "👍🏽", "<:bread:1234>"]
+        );
+    }
+
+    #[test]
+    fn not_emoji() {
+        for input in ["🍞a", "!", "7", "🍞 x", "\u{301}", " \u{301}"] {
+            assert!(parse_emotes(input).is_err(), "{:?}", input);
+        }
+        assert_eq!(parse_emotes("🍞 ,"), Err(BadEmoji(",".to_string())));
+        assert!("a".parse::<Emoji>().is_err());
+        assert_eq!(
+            parse("©️ ™ ♥️ ⬆️ #️⃣ 🀄"),
+            vec!["©️", "™", "♥️", "⬆️", "#️⃣", "🀄"]
+        );
+    }
+
+    #[test]
+    fn duplicates() {
+        assert_eq!(parse("🍞🍞🥖"), vec!["🍞", "🥖"]);
+    }
+}
@@ -0,0 +1,340 @@
+use crate::action::Action;
+use crate::reaction_set::ReactionSet;
+use crate::target::compile_regex;
+use regex::{Regex, RegexSet};
+use serenity::model::id::UserId;
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+/// The outcome of running a message through a guild's rules.
+pub struct Evaluation {
+    /// Rules whose user and regex matched the message.
+    pub matched: Vec<i64>,
+    /// Matching rules whose reactions overlapped with an earlier rule and were dropped.
+    pub dropped: Vec<i64>,
+    /// Matching rules left out because the message had no room for their
+    /// reactions under discord's limit.
+    pub truncated: Vec<i64>,
+    /// The reactions each rule that fired added, in rule order.
+    pub by_rule: Vec<(i64, Vec<String>)>,
+    /// Rules left out because their stored regex doesn't compile.
+    pub invalid: Vec<i64>,
+    /// Whether a rule matched the letters typed one message at a time.
+    pub column_matched: bool,
+    /// The reactions that would be applied.
+    pub reactions: ReactionSet,
+}
+
+impl Evaluation {
+    /// Rules that matched and had their reactions added.
+    pub fn fired(&self) -> Vec<i64> {
+        self.by_rule.iter().map(|(id, _)| *id).collect()
+    }
+
+    /// Multi-line explanation of the evaluation, for the dry-run commands.
+    pub fn report(&self) -> String {
+        let ids = |ids: &[i64]| {
+            if ids.is_empty() {
+                "none".to_string()
+            } else {
+                ids.iter()
+                    .map(|id| format!("#{}", id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        };
+        let reactions = self.reactions.as_list().concat();
+
+        // Only mention the reaction limit when it came into play
+        let truncated = if self.truncated.is_empty() {
+            String::new()
+        } else {
+            format!(
+                "\nLeft out for the reaction limit: {}",
+                ids(&self.truncated)
+            )
+        };
+
+        let invalid = if self.invalid.is_empty() {
+            String::new()
+        } else {
+            format!("\nSkipped for an invalid regex: {}", ids(&self.invalid))
+        };
+
+        format!(
+            "Matched: {}\nDropped for overlapping emoji: {}{}{}\nReactions: {}",
+            ids(&self.matched),
+            ids(&self.dropped),
+            truncated,
+            invalid,
+            if reactions.is_empty() {
+                "none"
+            } else {
+                &reactions
+            }
+        )
+    }
+}
+
+/// A guild's rules with their regexes compiled up front, so that messages can
+/// be checked without touching the database or recompiling anything. All the
+/// patterns are combined into a `RegexSet` so each message is scanned once.
+pub struct CompiledRules {
+    /// Each rule along with the index of its pattern in `regexes`, if it has one.
+    rules: Vec<(Action, Option<usize>)>,
+    regexes: Vec<Regex>,
+    set: Option<RegexSet>,
+    /// Rules whose stored regex doesn't compile, which never fire.
+    invalid: Vec<i64>,
+    /// Authors whose messages no rule applies to.
+    immune: HashSet<UserId>,
+}
+
+impl CompiledRules {
+    pub fn new(actions: Vec<Action>) -> Self {
+        let mut rules = Vec::new();
+        let mut regexes = Vec::new();
+        let mut invalid = Vec::new();
+        for action in actions {
+            let index = match &action.regex {
+                Some(s) => match compile_regex(s) {
+                    Ok(r) => {
+                        regexes.push(r);
+                        Some(regexes.len() - 1)
+                    }
+                    Err(e) => {
+                        // A rule that somehow holds a bad regex shouldn't take out the rest
+                        println!(
+                            "Skipping target #{} in guild {} with invalid regex {:?}: {}",
+                            action.id, action.guild_id, s, e
+                        );
+                        invalid.push(action.id);
+                        continue;
+                    }
+                },
+                None => None,
+            };
+            rules.push((action, index));
+        }
+
+        // Every pattern compiled on its own, but together they can still blow
+        // the size limit, in which case fall back to checking them one by one
+        let set = match RegexSet::new(regexes.iter().map(|r| r.as_str())) {
+            Ok(set) => Some(set),
+            Err(e) => {
+                println!("Checking regexes individually: {}", e);
+                None
+            }
+        };
+
+        Self {
+            rules,
+            regexes,
+            set,
+            invalid,
+            immune: HashSet::new(),
+        }
+    }
+
+    /// Leave messages from these users alone, see `immunity`.
+    pub fn with_immune(mut self, users: impl IntoIterator<Item = UserId>) -> Self {
+        self.immune = users.into_iter().collect();
+        self
+    }
+
+    pub fn is_immune(&self, uid: UserId) -> bool {
+        self.immune.contains(&uid)
+    }
+
+    /// Which of the patterns match `text`, by index into `regexes`.
+    fn matches(&self, text: &str) -> Vec<bool> {
+        match &self.set {
+            Some(set) => {
+                let matches = set.matches(text);
+                (0..self.regexes.len())
+                    .map(|i| matches.matched(i))
+                    .collect()
+            }
+            None => self.regexes.iter().map(|r| r.is_match(text)).collect(),
+        }
+    }
+
+    /// Gather the reactions for a message from the enabled, unexpired rules
+    /// that apply to its author, unless they're immune. If a rule has a regex,
+    /// it is checked against both the message and the `column` of single
+    /// letters built up by the author, otherwise the rule always matches.
+    /// Only new messages are evaluated, so none of discord's reaction limit is
+    /// taken yet. Once it runs out, the remaining rules are left out, so older
+    /// rules take priority.
+    pub fn evaluate(
+        &self,
+        uid: UserId,
+        content: &str,
+        column: Option<&str>,
+        time: SystemTime,
+    ) -> Evaluation {
+        let mut evaluation = Evaluation {
+            matched: Vec::new(),
+            dropped: Vec::new(),
+            truncated: Vec::new(),
+            by_rule: Vec::new(),
+            invalid: self.invalid.clone(),
+            column_matched: false,
+            reactions: ReactionSet::new(),
+        };
+
+        let content_matches = self.matches(content);
+        let column_matches = column.map(|c| self.matches(c));
+
+        let immune = self.is_immune(uid);
+        let applicable = self.rules.iter().filter(|(action, _)| {
+            !immune
+                && action.enabled
+                && action.user_id.is_none_or(|u| u == uid.get() as i64)
+                && action.expiration.is_none_or(|e| e > time)
+        });
+        for (action, index) in applicable {
+            let is_match = match index {
+                Some(i) => {
+                    let column_match = column_matches.as_ref().is_some_and(|m| m[*i]);
+                    evaluation.column_matched |= column_match;
+                    content_matches[*i] || column_match
+                }
+                None => true,
+            };
+
+            if is_match {
+                evaluation.matched.push(action.id);
+                if !evaluation.reactions.has_room(&action.reactions) {
+                    evaluation.truncated.push(action.id);
+                } else if evaluation.reactions.add_reactions(&action.reactions) {
+                    evaluation
+                        .by_rule
+                        .push((action.id, action.reactions.clone()));
+                } else {
+                    evaluation.dropped.push(action.id);
+                }
+            }
+        }
+
+        evaluation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(actions: &[Action], content: &str, column: Option<&str>) -> Evaluation {
+        CompiledRules::new(actions.to_vec()).evaluate(
+            UserId::new(5),
+            content,
+            column,
+            SystemTime::now(),
+        )
+    }
+
+    fn action(id: i64, regex: Option<&str>, reactions: &str) -> Action {
+        Action {
+            id,
+            guild_id: 1,
+            user_id: None,
+            regex: regex.map(|r| r.to_string()),
+            reactions: reactions.chars().map(|c| c.to_string()).collect(),
+            expiration: None,
+            enabled: true,
+            created_by: None,
+            created_at: None,
+            hit_count: 0,
+            last_fired_at: None,
+            failure_count: 0,
+        }
+    }
+
+    #[test]
+    fn regex_filters() {
+        let actions = [
+            action(1, Some("bread"), "🍞"),
+            action(2, Some("toast"), "🥖"),
+        ];
+        let evaluation = evaluate(&actions, "I like bread", None);
+        assert_eq!(evaluation.matched, vec![1]);
+        assert!(evaluation.dropped.is_empty());
+        assert_eq!(evaluation.reactions.as_list().concat(), "🍞");
+    }
+
+    #[test]
+    fn overlapping_dropped() {
+        let actions = [action(1, None, "🍞"), action(2, None, "🍞🥖")];
+        let evaluation = evaluate(&actions, "anything", None);
+        assert_eq!(evaluation.matched, vec![1, 2]);
+        assert_eq!(evaluation.dropped, vec![2]);
+        assert_eq!(evaluation.fired(), vec![1]);
+        assert_eq!(evaluation.reactions.as_list().concat(), "🍞");
+    }
+
+    #[test]
+    fn reaction_limit() {
+        let actions: Vec<Action> = ('a'..='y')
+            .enumerate()
+            .map(|(i, c)| action(i as i64 + 1, None, &c.to_string()))
+            .collect();
+        let evaluation = evaluate(&actions, "anything", None);
+        assert_eq!(evaluation.fired(), (1..=20).collect::<Vec<_>>());
+        assert_eq!(evaluation.truncated, (21..=25).collect::<Vec<_>>());
+
+        // A later rule that still fits is added after one that doesn't
+        let mut actions: Vec<Action> = ('a'..='s')
+            .enumerate()
+            .map(|(i, c)| action(i as i64 + 1, None, &c.to_string()))
+            .collect();
+        actions.push(action(20, None, "tu"));
+        actions.push(action(21, None, "v"));
+        let evaluation = evaluate(&actions, "anything", None);
+        assert_eq!(evaluation.truncated, vec![20]);
+        assert_eq!(
+            evaluation.by_rule.last(),
+            Some(&(21, vec!["v".to_string()]))
+        );
+    }
+
+    #[test]
+    fn column_match() {
+        let actions = [action(1, Some("bread"), "🍞")];
+        let evaluation = evaluate(&actions, "d", Some("bread"));
+        assert_eq!(evaluation.matched, vec![1]);
+        assert!(evaluation.column_matched);
+    }
+
+    #[test]
+    fn skips_other_users_and_bad_regex() {
+        let mut other = action(1, None, "🍞");
+        other.user_id = Some(6);
+        let mut mine = action(2, None, "🥖");
+        mine.user_id = Some(5);
+        let actions = [other, mine, action(3, Some("("), "🥐")];
+        let evaluation = evaluate(&actions, "(", None);
+        assert_eq!(evaluation.matched, vec![2]);
+        assert_eq!(evaluation.invalid, vec![3]);
+        assert!(evaluation
+            .report()
+            .contains("Skipped for an invalid regex: #3"));
+    }
+
+    #[test]
+    fn immune_author() {
+        let mut mine = action(1, None, "🍞");
+        mine.user_id = Some(5);
+        let rules = CompiledRules::new(vec![mine, action(2, Some("bread"), "🥖")])
+            .with_immune([UserId::new(5)]);
+        let now = SystemTime::now();
+        assert!(rules
+            .evaluate(UserId::new(5), "bread", None, now)
+            .matched
+            .is_empty());
+        assert_eq!(
+            rules.evaluate(UserId::new(6), "bread", None, now).matched,
+            vec![2]
+        );
+    }
+}
@@ -0,0 +1,401 @@
+use crate::action::Action;
+use crate::store::{RuleStore, StoreError};
+use crate::target::{Target, TargetBuilderError};
+use serde::{Deserialize, Serialize};
+use serenity::model::id::{GuildId, UserId};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// Pick the format from a file name's extension.
+    pub fn from_file_name(name: &str) -> Option<Format> {
+        name.rsplit_once('.').and_then(|(_, ext)| ext.parse().ok())
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Toml => "toml",
+            Format::Json => "json",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = ExportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            _ => Err(ExportError::UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    UnknownFormat(String),
+    TomlRead(toml::de::Error),
+    TomlWrite(toml::ser::Error),
+    Json(serde_json::Error),
+    /// A rule in the document failed validation, counting from 1.
+    BadRule(usize, TargetBuilderError),
+    /// The document names guild 0 and no other guild was given.
+    ZeroGuild,
+    Store(StoreError),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            ExportError::UnknownFormat(s) => write!(f, "Unknown format {}, use toml or json", s),
+            ExportError::TomlRead(e) => write!(f, "Invalid TOML: {}", e),
+            ExportError::TomlWrite(e) => write!(f, "Couldn't write TOML: {}", e),
+            ExportError::Json(e) => write!(f, "Invalid JSON: {}", e),
+            ExportError::BadRule(n, e) => write!(f, "Rule {}: {}", n, e),
+            ExportError::ZeroGuild => write!(f, "The guild can't be 0"),
+            ExportError::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<StoreError> for ExportError {
+    fn from(e: StoreError) -> Self {
+        ExportError::Store(e)
+    }
+}
+
+/// A guild's rules as written to an export file. Times are seconds since the
+/// unix epoch.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub guild: u64,
+    #[serde(default)]
+    pub rules: Vec<ExportedRule>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedRule {
+    /// The rule's ID where it was exported from. Imported rules get new IDs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    pub reactions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<u64>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<u64>,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// How to bring rules from an export into a guild.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ImportOptions {
+    /// Import into this guild instead of the one the rules were exported from.
+    pub guild: Option<GuildId>,
+    /// Leave out rules that have already expired, instead of refusing them.
+    pub skip_expired: bool,
+    /// Accept rules whose regex matches every message, as when adding one.
+    pub allow_empty_match: bool,
+    /// Credit every rule to this user instead of the creators the file names.
+    /// Imports through discord credit the importing member, since a file can
+    /// name anyone and rules count against their creator's quota.
+    pub creator: Option<UserId>,
+}
+
+/// A user ID from an export file, refusing 0.
+fn user_id(id: u64, field: &'static str) -> Result<UserId, TargetBuilderError> {
+    match id {
+        0 => Err(TargetBuilderError::ZeroId(field)),
+        id => Ok(UserId::new(id)),
+    }
+}
+
+impl RuleSet {
+    pub fn from_actions(gid: GuildId, actions: &[Action]) -> RuleSet {
+        RuleSet {
+            guild: gid.get(),
+            rules: actions
+                .iter()
+                .map(|a| ExportedRule {
+                    id: Some(a.id),
+                    user: a.user_id.map(|u| u as u64),
+                    regex: a.regex.clone(),
+                    reactions: a.reactions.clone(),
+                    expiration: a
+                        .expiration
+                        .and_then(|e| e.duration_since(UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs()),
+                    enabled: a.enabled,
+                    created_by: a.created_by.map(|u| u as u64),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn parse(text: &str, format: Format) -> Result<RuleSet, ExportError> {
+        match format {
+            Format::Toml => toml::from_str(text).map_err(ExportError::TomlRead),
+            Format::Json => serde_json::from_str(text).map_err(ExportError::Json),
+        }
+    }
+
+    pub fn write(&self, format: Format) -> Result<String, ExportError> {
+        match format {
+            Format::Toml => toml::to_string_pretty(self).map_err(ExportError::TomlWrite),
+            Format::Json => serde_json::to_string_pretty(self).map_err(ExportError::Json),
+        }
+    }
+
+    /// Validate every rule the same way as a newly added one, returning the
+    /// targets along with whether each should be enabled.
+    pub fn targets(
+        &self,
+        options: ImportOptions,
+        time: SystemTime,
+    ) -> Result<Vec<(Target, bool)>, ExportError> {
+        self.targets_sparing(options, &[], time)
+    }
+
+    /// Like `targets`, refusing rules that single out any of the `immune` users.
+    fn targets_sparing(
+        &self,
+        options: ImportOptions,
+        immune: &[UserId],
+        time: SystemTime,
+    ) -> Result<Vec<(Target, bool)>, ExportError> {
+        let gid = self.guild(options)?;
+        let mut targets = Vec::new();
+        for (n, rule) in self.rules.iter().enumerate() {
+            let bad_rule = |e| ExportError::BadRule(n + 1, e);
+            let expiration = match rule.expiration {
+                Some(e) => Some(
+                    UNIX_EPOCH
+                        .checked_add(Duration::from_secs(e))
+                        .ok_or(bad_rule(TargetBuilderError::ExpirationOutOfRange))?,
+                ),
+                None => None,
+            };
+            if options.skip_expired && expiration.is_some_and(|e| e <= time) {
+                continue;
+            }
+
+            let mut builder = Target::builder()
+                .set_guild(gid)
+                .set_emotes(&rule.reactions.join(" "))
+                .set_immune(immune)
+                .allow_empty_match(options.allow_empty_match);
+            if let Some(user) = rule.user {
+                builder = builder.set_user(user_id(user, "user").map_err(bad_rule)?);
+            }
+            if let Some(regex) = &rule.regex {
+                builder = builder.set_regex(regex);
+            }
+            if let Some(expiration) = expiration {
+                builder = builder.set_expiration_time(expiration);
+            }
+            let creator = match (options.creator, rule.created_by) {
+                (Some(creator), _) => Some(creator),
+                (None, Some(creator)) => Some(user_id(creator, "created_by").map_err(bad_rule)?),
+                (None, None) => None,
+            };
+            if let Some(creator) = creator {
+                builder = builder.set_creator(creator);
+            }
+            let target = builder.build().map_err(bad_rule)?;
+            targets.push((target, rule.enabled));
+        }
+        Ok(targets)
+    }
+
+    /// The guild the rules are imported into.
+    fn guild(&self, options: ImportOptions) -> Result<GuildId, ExportError> {
+        match (options.guild, self.guild) {
+            (Some(gid), _) => Ok(gid),
+            (None, 0) => Err(ExportError::ZeroGuild),
+            (None, gid) => Ok(GuildId::new(gid)),
+        }
+    }
+
+    /// Validate all the rules, then add them to `store` together, returning the
+    /// new IDs. Rules against users immune in the guild are refused.
+    pub fn import(
+        &self,
+        store: &dyn RuleStore,
+        options: ImportOptions,
+        time: SystemTime,
+    ) -> Result<Vec<i64>, ExportError> {
+        let immune: Vec<UserId> = store
+            .immune_users(self.guild(options)?)?
+            .iter()
+            .map(|i| UserId::new(i.user_id as u64))
+            .collect();
+        let targets = self.targets_sparing(options, &immune, time)?;
+        Ok(store.insert_all(&targets, time)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    #[test]
+    fn round_trip_with_remap() {
+        let store = MemoryStore::new();
+        let now = SystemTime::now();
+        let target = Target::builder()
+            .set_guild(GuildId::new(1))
+            .set_user(UserId::new(5))
+            .set_regex("bread")
+            .set_emotes("🍞 <:bread:1234>")
+            .build()
+            .unwrap();
+        let id = store.insert(&target, now).unwrap();
+        store.set_enabled(GuildId::new(1), id, false).unwrap();
+
+        let actions = store.list(GuildId::new(1), now).unwrap();
+        for format in [Format::Toml, Format::Json] {
+            let text = RuleSet::from_actions(GuildId::new(1), &actions)
+                .write(format)
+                .unwrap();
+            let options = ImportOptions {
+                guild: Some(GuildId::new(2)),
+                ..Default::default()
+            };
+            RuleSet::parse(&text, format)
+                .unwrap()
+                .import(&store, options, now)
+                .unwrap();
+        }
+
+        let imported = store.list(GuildId::new(2), now).unwrap();
+        assert_eq!(imported.len(), 2);
+        for action in &imported {
+            assert_eq!(action.user_id, Some(5));
+            assert_eq!(action.reactions, ["🍞", "<:bread:1234>"]);
+            assert_eq!(action.regex.as_deref(), Some("bread"));
+            assert!(!action.enabled);
+        }
+    }
+
+    #[test]
+    fn expired_and_invalid_rules() {
+        let text = r#"
+            guild = 1
+
+            [[rules]]
+            user = 5
+            reactions = ["🍞"]
+            expiration = 1
+
+            [[rules]]
+            regex = "bread"
+            reactions = ["🥖"]
+        "#;
+        let rules = RuleSet::parse(text, Format::Toml).unwrap();
+        let now = SystemTime::now();
+        assert!(matches!(
+            rules.targets(ImportOptions::default(), now),
+            Err(ExportError::BadRule(1, TargetBuilderError::AlreadyExpired))
+        ));
+        let options = ImportOptions {
+            skip_expired: true,
+            ..Default::default()
+        };
+        assert_eq!(rules.targets(options, now).unwrap().len(), 1);
+
+        let everything = RuleSet::parse(
+            r#"{"guild": 1, "rules": [{"regex": ".*", "reactions": ["🍞"]}]}"#,
+            Format::Json,
+        )
+        .unwrap();
+        assert!(matches!(
+            everything.targets(ImportOptions::default(), now),
+            Err(ExportError::BadRule(
+                1,
+                TargetBuilderError::RegexMatchesEverything
+            ))
+        ));
+        let options = ImportOptions {
+            allow_empty_match: true,
+            ..Default::default()
+        };
+        assert_eq!(everything.targets(options, now).unwrap().len(), 1);
+
+        let bad = RuleSet::parse(
+            r#"{"guild": 1, "rules": [{"reactions": ["🍞"]}]}"#,
+            Format::Json,
+        )
+        .unwrap();
+        assert!(matches!(
+            bad.targets(options, now),
+            Err(ExportError::BadRule(
+                1,
+                TargetBuilderError::MissingUserAndRegex
+            ))
+        ));
+    }
+
+    #[test]
+    fn untrusted_fields() {
+        let now = SystemTime::now();
+        let parse = |rule: &str| {
+            RuleSet::parse(
+                &format!(
+                    r#"{{"guild": 1, "rules": [{{"regex": "bread", "reactions": ["🍞"], {}}}]}}"#,
+                    rule
+                ),
+                Format::Json,
+            )
+            .unwrap()
+        };
+        assert!(matches!(
+            parse(r#""expiration": 18446744073709551615"#).targets(ImportOptions::default(), now),
+            Err(ExportError::BadRule(
+                1,
+                TargetBuilderError::ExpirationOutOfRange
+            ))
+        ));
+        assert!(matches!(
+            parse(r#""user": 0"#).targets(ImportOptions::default(), now),
+            Err(ExportError::BadRule(1, TargetBuilderError::ZeroId("user")))
+        ));
+        assert!(matches!(
+            parse(r#""created_by": 0"#).targets(ImportOptions::default(), now),
+            Err(ExportError::BadRule(
+                1,
+                TargetBuilderError::ZeroId("created_by")
+            ))
+        ));
+        let zero_guild = RuleSet::parse(r#"{"guild": 0, "rules": []}"#, Format::Json).unwrap();
+        assert!(matches!(
+            zero_guild.targets(ImportOptions::default(), now),
+            Err(ExportError::ZeroGuild)
+        ));
+
+        let options = ImportOptions {
+            creator: Some(UserId::new(9)),
+            ..Default::default()
+        };
+        let targets = parse(r#""created_by": 5"#).targets(options, now).unwrap();
+        assert_eq!(targets[0].0.get_creator(), Some(9));
+    }
+}
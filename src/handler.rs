@@ -1,61 +1,1028 @@
-extern crate diesel;
-use crate::action::Action;
-use crate::reaction_set::ReactionSet;
-use crate::target::{Target, TargetBuilderError};
-use diesel::insert_into;
-use diesel::pg::PgConnection;
-use diesel::prelude::*;
-use regex::Regex;
+use crate::emoji::Emoji;
+use crate::export::{Format, ImportOptions, RuleSet};
+use crate::reaction_set::MAX_REACTIONS;
+use crate::rule_cache::RuleCache;
+use crate::settings::Settings;
+use crate::shutdown::InFlight;
+use crate::store::{self, RuleStore};
+use crate::systemd;
+use crate::target::{compile_regex, Target, TargetBuilder, TargetBuilderError, MAX_PATTERN_LEN};
 use serenity::{
     async_trait,
     builder::{
-        CreateCommand, CreateCommandOption, CreateInteractionResponse,
-        CreateInteractionResponseMessage,
+        AutocompleteChoice, CreateActionRow, CreateAttachment, CreateAutocompleteResponse,
+        CreateButton, CreateCommand, CreateCommandOption, CreateEmbed, CreateEmbedFooter,
+        CreateInteractionResponse, CreateInteractionResponseMessage,
     },
+    http::HttpError,
     model::{
-        application::{Command, CommandDataOptionValue, CommandOptionType, Interaction},
-        channel::Message,
+        application::{
+            ButtonStyle, Command, CommandDataOption, CommandDataOptionValue, CommandInteraction,
+            CommandOptionType, Interaction,
+        },
+        channel::{Attachment, Message},
         gateway::Ready,
         id::{GuildId, UserId},
+        permissions::Permissions,
     },
     prelude::*,
 };
 
-use serenity::all::ReactionType;
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::sync::Mutex;
-use std::time::SystemTime;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, SystemTime};
 use unicode_segmentation::UnicodeSegmentation;
 
+/// Most rules shown on a single page of `/list_targets` and `/target_history`.
+const LIST_PAGE_SIZE: usize = 10;
+
+/// Discord refuses embeds with more characters than this in total.
+const MAX_EMBED_LEN: usize = 6000;
+
+/// Discord caps autocomplete responses at 25 choices of at most 100 characters.
+const MAX_CHOICES: usize = 25;
+const MAX_CHOICE_LEN: usize = 100;
+
+/// Discord refuses embed field values longer than this many characters.
+const MAX_FIELD_LEN: usize = 1024;
+
+/// Cut `text` down to `max` characters, marking that something was left out.
+fn truncate(text: String, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text;
+    }
+    let mut cut: String = text.chars().take(max - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// Largest rule file `/import_targets` will download, in bytes.
+const MAX_IMPORT_SIZE: u32 = 1024 * 1024;
+
+/// Reactions a rule can have refused in a row for unknown emoji before it's
+/// paused.
+const MAX_REACTION_FAILURES: i64 = 5;
+
+/// Discord's error code for reacting with an emoji it doesn't know, such as one
+/// from a server the bot isn't in.
+const UNKNOWN_EMOJI: isize = 10014;
+
+/// Whether discord refused a reaction because of the emoji itself, as opposed
+/// to permissions or connection trouble.
+fn unknown_emoji(error: &SerenityError) -> bool {
+    matches!(
+        error,
+        SerenityError::Http(HttpError::UnsuccessfulRequest(response))
+            if response.error.code == UNKNOWN_EMOJI
+    )
+}
+
+/// Immune members shown by `/immunity list`, to stay under discord's message length.
+const MAX_IMMUNE_LISTED: usize = 25;
+
+/// Look up an integer option on a slash command by name.
+fn integer_option(command: &CommandInteraction, name: &str) -> Option<i64> {
+    command
+        .data
+        .options
+        .iter()
+        .find_map(|entry| match &entry.value {
+            CommandDataOptionValue::Integer(int) if entry.name == name => Some(*int),
+            _ => None,
+        })
+}
+
+/// Apply the rule options shared by the target commands to a builder.
+fn apply_target_options(mut builder: TargetBuilder, command: &CommandInteraction) -> TargetBuilder {
+    for entry in &command.data.options {
+        match entry.name.as_ref() {
+            "emotes" => {
+                if let CommandDataOptionValue::String(s) = &entry.value {
+                    builder = builder.set_emotes(s)
+                }
+            }
+            "duration" => {
+                if let CommandDataOptionValue::Integer(int) = &entry.value {
+                    builder = builder.set_expiration(*int as u64)
+                }
+            }
+            "user" => {
+                if let CommandDataOptionValue::User(user) = &entry.value {
+                    builder = builder.set_user(*user)
+                }
+            }
+            "regex" => {
+                if let CommandDataOptionValue::String(s) = &entry.value {
+                    builder = builder.set_regex(s)
+                }
+            }
+            "clear_user" => {
+                if let CommandDataOptionValue::Boolean(true) = &entry.value {
+                    builder = builder.clear_user()
+                }
+            }
+            "allow_empty" => {
+                if let CommandDataOptionValue::Boolean(allow) = &entry.value {
+                    builder = builder.allow_empty_match(*allow)
+                }
+            }
+            "clear_regex" => {
+                if let CommandDataOptionValue::Boolean(true) = &entry.value {
+                    builder = builder.clear_regex()
+                }
+            }
+            "id" => {}
+            _ => println!("Unexpected entry name: {}", entry.name),
+        }
+    }
+    builder
+}
+
+/// Explain to the user why their rule was rejected.
+fn build_error_message(e: TargetBuilderError) -> String {
+    match e {
+        TargetBuilderError::BadRegex(_) => "Your regex game is weak, bitch. Refer to \
+            https://docs.rs/regex/latest/regex/index.html#syntax"
+            .to_string(),
+        TargetBuilderError::MissingUserAndRegex => {
+            "Need either a user or a regex or both... bitch".to_string()
+        }
+        TargetBuilderError::AlreadyExpired => {
+            "That target has already expired, give it a new duration".to_string()
+        }
+        TargetBuilderError::RegexTooLong(len) => format!(
+            "That regex is {} characters long, keep it to {}",
+            len, MAX_PATTERN_LEN
+        ),
+        TargetBuilderError::RegexTooBig => {
+            "That regex is too complex, try something simpler".to_string()
+        }
+        TargetBuilderError::RegexMatchesEverything => "That regex matches empty text, so it \
+            would react to every message. Set allow_empty if that's what you want"
+            .to_string(),
+        TargetBuilderError::ImmuneUser(uid) => {
+            format!("<@{}> is immune to targets in this server", uid)
+        }
+        TargetBuilderError::TooManyEmotes(n) => format!(
+            "That's {} emoji, discord only shows {} reactions on a message",
+            n, MAX_REACTIONS
+        ),
+        TargetBuilderError::BadEmote(e) => format!(
+            "{}. Use unicode emoji or this server's emoji, separated by spaces if needed",
+            e
+        ),
+        e => e.to_string(),
+    }
+}
+
+/// Look up a string option on a slash command by name.
+fn string_option<'a>(command: &'a CommandInteraction, name: &str) -> Option<&'a str> {
+    command
+        .data
+        .options
+        .iter()
+        .find_map(|entry| match &entry.value {
+            CommandDataOptionValue::String(s) if entry.name == name => Some(s.as_str()),
+            _ => None,
+        })
+}
+
+/// Look up a user option on a slash command by name.
+fn user_option(command: &CommandInteraction, name: &str) -> Option<UserId> {
+    command
+        .data
+        .options
+        .iter()
+        .find_map(|entry| match &entry.value {
+            CommandDataOptionValue::User(user) if entry.name == name => Some(*user),
+            _ => None,
+        })
+}
+
+/// Where a page starts when asked to start at `start` of `total` entries.
+fn page_start(start: usize, total: usize) -> usize {
+    start.min(total.saturating_sub(1))
+}
+
+/// The fields that fit on one page, cut to discord's limits. A page ends early
+/// when the next field would take the embed past `budget` characters, but
+/// always shows at least one.
+fn fit_page(fields: Vec<(String, String)>, budget: usize) -> Vec<(String, String)> {
+    let mut used = 0;
+    let mut page = Vec::new();
+    for (name, value) in fields.into_iter().take(LIST_PAGE_SIZE) {
+        let value = truncate(value, MAX_FIELD_LEN);
+        used += name.chars().count() + value.chars().count();
+        if used > budget && !page.is_empty() {
+            break;
+        }
+        page.push((name, value));
+    }
+    page
+}
+
+/// Show a page of entries in an embed, with buttons to move between pages.
+/// `fields` are the entries from `start` on, out of `total`. The buttons'
+/// custom IDs are `{prefix}:{start}`.
+fn paged_embed(
+    title: &str,
+    prefix: &str,
+    fields: Vec<(String, String)>,
+    start: usize,
+    total: usize,
+) -> CreateInteractionResponseMessage {
+    // Leave room for the title and the longest footer this listing can have
+    let footer_len = format!("Rules {}-{} of {}", total, total, total).len();
+    let budget = MAX_EMBED_LEN - title.chars().count() - footer_len;
+    let fields = fit_page(fields, budget);
+    let end = start + fields.len();
+
+    let embed = CreateEmbed::new()
+        .title(title)
+        .footer(CreateEmbedFooter::new(format!(
+            "Rules {}-{} of {}",
+            start + 1,
+            end,
+            total
+        )))
+        .fields(fields.into_iter().map(|(name, value)| (name, value, false)));
+
+    let buttons = CreateActionRow::Buttons(vec![
+        CreateButton::new(format!(
+            "{}:{}",
+            prefix,
+            start.saturating_sub(LIST_PAGE_SIZE)
+        ))
+        .label("Previous")
+        .style(ButtonStyle::Secondary)
+        .disabled(start == 0),
+        CreateButton::new(format!("{}:{}", prefix, end))
+            .label("Next")
+            .style(ButtonStyle::Secondary)
+            .disabled(end >= total),
+    ]);
+
+    CreateInteractionResponseMessage::new()
+        .content("")
+        .embed(embed)
+        .components(vec![buttons])
+}
+
+/// Look up a boolean option on a slash command by name.
+fn bool_option(command: &CommandInteraction, name: &str) -> Option<bool> {
+    command
+        .data
+        .options
+        .iter()
+        .find_map(|entry| match &entry.value {
+            CommandDataOptionValue::Boolean(b) if entry.name == name => Some(*b),
+            _ => None,
+        })
+}
+
+/// Look up an attachment option on a slash command by name.
+fn attachment_option<'a>(command: &'a CommandInteraction, name: &str) -> Option<&'a Attachment> {
+    command
+        .data
+        .options
+        .iter()
+        .find_map(|entry| match &entry.value {
+            CommandDataOptionValue::Attachment(id) if entry.name == name => {
+                command.data.resolved.attachments.get(id)
+            }
+            _ => None,
+        })
+}
+
+/// The subcommand used and its options, for commands split into subcommands.
+fn subcommand(command: &CommandInteraction) -> Option<(&str, &[CommandDataOption])> {
+    command
+        .data
+        .options
+        .first()
+        .and_then(|entry| match &entry.value {
+            CommandDataOptionValue::SubCommand(options) => {
+                Some((entry.name.as_str(), &options[..]))
+            }
+            _ => None,
+        })
+}
+
+/// Who may use a slash command, see `GuildSettings` for the roles involved.
+#[derive(Debug, PartialEq, Eq)]
+enum Access {
+    Anyone,
+    Create,
+    Manage,
+}
+
+impl Access {
+    fn of(command: &CommandInteraction) -> Access {
+        match command.data.name.as_str() {
+            "target_user" | "target_regex" => Access::Create,
+            "edit_target" | "pause_target" | "resume_target" | "remove_target"
+            | "import_targets" | "immunity" => Access::Manage,
+            "target_history" if integer_option(command, "restore").is_some() => Access::Manage,
+            _ => Access::Anyone,
+        }
+    }
+}
+
+/// Mention each of the roles, e.g. for telling users which roles they lack.
+fn role_mentions(roles: &[u64]) -> String {
+    roles
+        .iter()
+        .map(|r| format!("<@&{}>", r))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 pub struct Handler {
-    db_con: Arc<Mutex<PgConnection>>,
+    store: Arc<dyn RuleStore>,
+    cache: RuleCache,
     letter_chain: Arc<Mutex<HashMap<GuildId, (UserId, String)>>>,
+    dm_commands: bool,
+    settings: Settings,
+    /// The bot's own user, known once connected.
+    own_id: OnceLock<UserId>,
+    in_flight: InFlight,
 }
 
 impl Handler {
-    pub fn new(db_con: Arc<Mutex<PgConnection>>) -> Self {
+    /// Rules are cached per guild and reloaded from `store` every `cache_refresh`,
+    /// or straight away when they are changed through the bot.
+    pub fn new(store: Arc<dyn RuleStore>, cache_refresh: Duration) -> Self {
         Self {
-            db_con,
+            cache: RuleCache::new(store.clone(), cache_refresh),
+            store,
             letter_chain: Arc::new(Mutex::new(HashMap::new())),
+            dm_commands: false,
+            settings: Settings::default(),
+            own_id: OnceLock::new(),
+            in_flight: InFlight::new(),
         }
     }
 
-    fn target(&self, target: Target) {
-        use crate::schema::actions::dsl::*;
-        let mut db = self.db_con.lock().unwrap();
-        let res = insert_into(actions)
-            .values((
-                guild_id.eq(target.get_guild().get() as i64),
-                user_id.eq(target.get_user().map(|x| x as i64)),
-                reactions.eq(target.get_emotes()),
-                expiration.eq(target.get_expiration()),
-                regex.eq(target.get_regex()),
-            ))
-            .execute(&mut *db);
+    /// Tracks the messages still being reacted to, for shutdown to wait on.
+    pub fn in_flight(&self) -> InFlight {
+        self.in_flight.clone()
+    }
+
+    /// Per-guild settings, such as which authors to ignore.
+    pub fn settings(mut self, settings: Settings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    /// Answer direct messages with the rules that target the sender, instead
+    /// of ignoring them.
+    pub fn dm_commands(mut self, enabled: bool) -> Self {
+        self.dm_commands = enabled;
+        self
+    }
 
-        if let Err(e) = res {
-            println!("Error inserting target {:?}! {}", target, e);
+    /// The letter chain only holds scratch state, so a panic while it was
+    /// held is no reason to stop tracking letters.
+    fn letter_chain(&self) -> MutexGuard<'_, HashMap<GuildId, (UserId, String)>> {
+        self.letter_chain
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Add the target described by a `/target_user` or `/target_regex`
+    /// command, returning the reply and whether only the user should see it.
+    async fn add_target(&self, command: &CommandInteraction) -> (String, bool) {
+        let Some(gid) = command.guild_id else {
+            return ("Targets only exist in servers".to_string(), false);
+        };
+        let immune = match self.immune_users(gid).await {
+            Ok(immune) => immune,
+            Err(message) => return (message, false),
+        };
+        let builder = Target::builder()
+            .set_guild(gid)
+            .set_creator(command.user.id)
+            .set_immune(&immune);
+        match apply_target_options(builder, command).build() {
+            Ok(target) => match self.creation_limit(command, &target).await {
+                Some(limit) => (limit, true),
+                None => match self.target(target).await {
+                    Ok(id) => (format!("Target #{} added", id), false),
+                    Err(message) => (message, false),
+                },
+            },
+            Err(e) => (build_error_message(e), false),
+        }
+    }
+
+    /// Members of the guild that rules can't single out. Failures are logged
+    /// and come back as the reply to give.
+    async fn immune_users(&self, gid: GuildId) -> Result<Vec<UserId>, String> {
+        match store::blocking(&self.store, move |s| s.immune_users(gid)).await {
+            Ok(immune) => Ok(immune
+                .iter()
+                .map(|i| UserId::new(i.user_id as u64))
+                .collect()),
+            Err(e) => {
+                println!("Error looking up immune users for {}: {}", gid, e);
+                Err("Couldn't check who is immune to targets, try again later".to_string())
+            }
+        }
+    }
+
+    /// `/breadbot optout` and `/breadbot optin`, for members to make
+    /// themselves immune to targets or lift it again. Immunity a moderator
+    /// granted can only be lifted by a moderator.
+    async fn opt_out(&self, gid: GuildId, uid: UserId, immune: bool) -> String {
+        let now = SystemTime::now();
+        let result = store::blocking(&self.store, move |s| {
+            if immune {
+                return s
+                    .add_immunity(gid, uid, Some(uid), now)
+                    .map(|added| added.then_some(true));
+            }
+            let current = s.immune_users(gid)?;
+            match current.iter().find(|i| i.user_id == uid.get() as i64) {
+                Some(i) if !i.self_added() => Ok(None),
+                Some(_) => s.remove_immunity(gid, uid).map(Some),
+                None => Ok(Some(false)),
+            }
+        })
+        .await;
+        match (result, immune) {
+            (Ok(Some(true)), true) => {
+                self.cache.invalidate(gid);
+                "You're now immune to targets here. No new targets can single you out, and \
+                your messages won't get reactions"
+                    .to_string()
+            }
+            (Ok(_), true) => "You're already immune to targets here".to_string(),
+            (Ok(Some(true)), false) => {
+                self.cache.invalidate(gid);
+                "You can be targeted again".to_string()
+            }
+            (Ok(Some(false)), false) => "You haven't opted out".to_string(),
+            (Ok(None), false) => {
+                "A moderator made you immune, ask one of them to lift it".to_string()
+            }
+            (Err(e), _) => {
+                println!("Error changing immunity of {} in {}: {}", uid, gid, e);
+                "Couldn't update your immunity, try again later".to_string()
+            }
+        }
+    }
+
+    /// `/immunity add`, `remove` and `list`, for moderators to manage who is
+    /// immune to targets.
+    async fn immunity(
+        &self,
+        gid: GuildId,
+        by: UserId,
+        action: &str,
+        options: &[CommandDataOption],
+    ) -> String {
+        let user = options.iter().find_map(|entry| match &entry.value {
+            CommandDataOptionValue::User(user) if entry.name == "user" => Some(*user),
+            _ => None,
+        });
+        let now = SystemTime::now();
+        match (action, user) {
+            ("list", _) => {
+                let immune = match store::blocking(&self.store, move |s| s.immune_users(gid)).await
+                {
+                    Ok(immune) => immune,
+                    Err(e) => {
+                        println!("Error looking up immune users for {}: {}", gid, e);
+                        return "Couldn't look up who is immune, try again later".to_string();
+                    }
+                };
+                if immune.is_empty() {
+                    return "Nobody is immune to targets here".to_string();
+                }
+                let mut lines: Vec<String> = immune
+                    .iter()
+                    .take(MAX_IMMUNE_LISTED)
+                    .map(|i| i.describe())
+                    .collect();
+                if immune.len() > MAX_IMMUNE_LISTED {
+                    lines.push(format!("and {} more", immune.len() - MAX_IMMUNE_LISTED));
+                }
+                lines.join("\n")
+            }
+            ("add", Some(uid)) => {
+                match store::blocking(&self.store, move |s| {
+                    s.add_immunity(gid, uid, Some(by), now)
+                })
+                .await
+                {
+                    Ok(true) => {
+                        self.cache.invalidate(gid);
+                        format!("<@{}> is now immune to targets", uid)
+                    }
+                    Ok(false) => format!("<@{}> is already immune", uid),
+                    Err(e) => {
+                        println!("Error adding immunity for {} in {}: {}", uid, gid, e);
+                        "Couldn't update the immunity list, try again later".to_string()
+                    }
+                }
+            }
+            ("remove", Some(uid)) => {
+                match store::blocking(&self.store, move |s| s.remove_immunity(gid, uid)).await {
+                    Ok(true) => {
+                        self.cache.invalidate(gid);
+                        format!("<@{}> can be targeted again", uid)
+                    }
+                    Ok(false) => format!("<@{}> wasn't immune", uid),
+                    Err(e) => {
+                        println!("Error removing immunity for {} in {}: {}", uid, gid, e);
+                        "Couldn't update the immunity list, try again later".to_string()
+                    }
+                }
+            }
+            _ => "Need the user to change".to_string(),
+        }
+    }
+
+    /// Add a new rule, returning its ID. Failures are logged and come back as
+    /// the reply to give.
+    async fn target(&self, target: Target) -> Result<i64, String> {
+        let inserted = target.clone();
+        let now = SystemTime::now();
+        match store::blocking(&self.store, move |s| s.insert(&inserted, now)).await {
+            Ok(id) => {
+                self.cache.invalidate(target.get_guild());
+                Ok(id)
+            }
+            Err(e) => {
+                println!("Error inserting target {:?}! {}", target, e);
+                Err("Couldn't add the target, try again later".to_string())
+            }
+        }
+    }
+
+    /// Build the page of the `/list_targets` output starting at the `start`th
+    /// rule, along with the buttons to move between pages.
+    async fn list_targets(&self, gid: GuildId, start: usize) -> CreateInteractionResponseMessage {
+        let now = SystemTime::now();
+        let targets = match store::blocking(&self.store, move |s| s.list(gid, now)).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                println!("Error listing targets for {}: {}", gid, e);
+                return CreateInteractionResponseMessage::new()
+                    .content("Couldn't look up the targets, try again later");
+            }
+        };
+
+        if targets.is_empty() {
+            return CreateInteractionResponseMessage::new()
+                .content("No active targets")
+                .embeds(Vec::new())
+                .components(Vec::new());
+        }
+
+        let start = page_start(start, targets.len());
+        let fields = targets
+            .iter()
+            .skip(start)
+            .take(LIST_PAGE_SIZE)
+            .map(|action| {
+                let value = format!(
+                    "User: {}\nRegex: {}\nReactions: {}\nExpires in: {}\n{}{}",
+                    action.user_mention(),
+                    action
+                        .regex
+                        .as_ref()
+                        .map(|r| format!("`{}`", r))
+                        .unwrap_or_else(|| "none".to_string()),
+                    action.reactions.concat(),
+                    action.time_remaining(now),
+                    action.stats(),
+                    if action.enabled { "" } else { "\nPaused" },
+                );
+                // Rules skipped by the cache for a regex that no longer compiles
+                let value = match action.regex.as_deref().map(compile_regex) {
+                    Some(Err(_)) => format!("{}\nInvalid regex, never fires", value),
+                    _ => value,
+                };
+                (format!("#{}", action.id), value)
+            })
+            .collect();
+        paged_embed(
+            "Active targets",
+            "list_targets",
+            fields,
+            start,
+            targets.len(),
+        )
+    }
+
+    /// Build the page of the `/target_history` output starting at the `start`th
+    /// archived rule.
+    async fn target_history(&self, gid: GuildId, start: usize) -> CreateInteractionResponseMessage {
+        let page = store::blocking(&self.store, move |s| {
+            let total = s.history_len(gid)?;
+            let start = page_start(start, total);
+            Ok((start, total, s.history(gid, start, LIST_PAGE_SIZE)?))
+        });
+        let (start, total, history) = match page.await {
+            Ok(page) => page,
+            Err(e) => {
+                println!("Error looking up target history for {}: {}", gid, e);
+                return CreateInteractionResponseMessage::new()
+                    .content("Couldn't look up the target history, try again later");
+            }
+        };
+
+        if history.is_empty() {
+            return CreateInteractionResponseMessage::new()
+                .content("No archived targets")
+                .embeds(Vec::new())
+                .components(Vec::new());
+        }
+
+        let fields = history
+            .iter()
+            .map(|archived| {
+                (
+                    format!(
+                        "Archive #{} (was #{})",
+                        archived.archive_id, archived.action.id
+                    ),
+                    format!(
+                        "{}\n{}\n{}",
+                        archived.action.summary(),
+                        archived.describe(),
+                        archived.action.stats()
+                    ),
+                )
+            })
+            .collect();
+        paged_embed("Target history", "target_history", fields, start, total)
+    }
+
+    /// Put an archived rule back as a new target, optionally with a new
+    /// duration in minutes.
+    async fn restore_target(
+        &self,
+        gid: GuildId,
+        archive_id: i64,
+        duration: Option<i64>,
+        by: UserId,
+    ) -> String {
+        let archived =
+            match store::blocking(&self.store, move |s| s.get_archived(gid, archive_id)).await {
+                Ok(Some(archived)) => archived,
+                Ok(None) => return format!("No archived target #{} in this server", archive_id),
+                Err(e) => {
+                    println!("Error looking up archived target {}: {}", archive_id, e);
+                    return "Couldn't look up the archived target, try again later".to_string();
+                }
+            };
+
+        let immune = match self.immune_users(gid).await {
+            Ok(immune) => immune,
+            Err(message) => return message,
+        };
+        let mut builder = TargetBuilder::from_action(&archived.action)
+            .set_creator(by)
+            .set_immune(&immune);
+        if let Some(duration) = duration {
+            builder = builder.set_expiration(duration as u64);
+        }
+        let target = match builder.build() {
+            Ok(target) => target,
+            Err(e) => return build_error_message(e),
+        };
+        let now = SystemTime::now();
+        match store::blocking(&self.store, move |s| s.insert(&target, now)).await {
+            Ok(new_id) => {
+                self.cache.invalidate(gid);
+                format!("Archived target #{} restored as #{}", archive_id, new_id)
+            }
+            Err(e) => {
+                println!("Error restoring archived target {}: {}", archive_id, e);
+                "Couldn't restore the target, try again later".to_string()
+            }
+        }
+    }
+
+    /// Attach the guild's active rules as a file in `format`.
+    async fn export_targets(
+        &self,
+        gid: GuildId,
+        format: Format,
+    ) -> CreateInteractionResponseMessage {
+        let now = SystemTime::now();
+        let text = match store::blocking(&self.store, move |s| s.list(gid, now)).await {
+            Ok(targets) => RuleSet::from_actions(gid, &targets).write(format),
+            Err(e) => {
+                println!("Error listing targets for {}: {}", gid, e);
+                return CreateInteractionResponseMessage::new()
+                    .content("Couldn't look up the targets, try again later");
+            }
+        };
+        match text {
+            Ok(text) => {
+                let name = format!("targets-{}.{}", gid, format.extension());
+                CreateInteractionResponseMessage::new()
+                    .add_file(CreateAttachment::bytes(text, name))
+            }
+            Err(e) => {
+                println!("Error exporting targets for {}: {}", gid, e);
+                CreateInteractionResponseMessage::new().content("Couldn't export the targets")
+            }
+        }
+    }
+
+    /// Add the rules from an uploaded export file to the guild.
+    async fn import_targets(&self, file: &Attachment, options: ImportOptions) -> String {
+        let Some(gid) = options.guild else {
+            return "Targets only exist in servers".to_string();
+        };
+        let Some(format) = Format::from_file_name(&file.filename) else {
+            return "Upload a .toml or .json file from /export_targets".to_string();
+        };
+        if file.size > MAX_IMPORT_SIZE {
+            return "That file is too big to be a rule export".to_string();
+        }
+        let text = match file.download().await.map(String::from_utf8) {
+            Ok(Ok(text)) => text,
+            Ok(Err(_)) => return "That file isn't text".to_string(),
+            Err(e) => {
+                println!("Error downloading {}: {}", file.url, e);
+                return "Couldn't download the file, try again later".to_string();
+            }
+        };
+        let rules = match RuleSet::parse(&text, format) {
+            Ok(rules) => rules,
+            Err(e) => return e.to_string(),
+        };
+
+        let now = SystemTime::now();
+        match store::blocking(&self.store, move |s| Ok(rules.import(s, options, now))).await {
+            Ok(Ok(ids)) => {
+                self.cache.invalidate(gid);
+                format!("Imported {} targets", ids.len())
+            }
+            Ok(Err(e)) => {
+                println!("Error importing targets for {}: {}", gid, e);
+                e.to_string()
+            }
+            Err(e) => {
+                println!("Error importing targets for {}: {}", gid, e);
+                "Couldn't import the targets, try again later".to_string()
+            }
+        }
+    }
+
+    /// Apply the options of an `/edit_target` command to an existing rule.
+    async fn edit_target(
+        &self,
+        gid: GuildId,
+        target_id: i64,
+        command: &CommandInteraction,
+    ) -> String {
+        let action = match store::blocking(&self.store, move |s| s.get(gid, target_id)).await {
+            Ok(Some(action)) => action,
+            Ok(None) => return format!("No target #{} in this server", target_id),
+            Err(e) => {
+                println!("Error looking up target {}: {}", target_id, e);
+                return "Couldn't look up the target, try again later".to_string();
+            }
+        };
+
+        let immune = match self.immune_users(gid).await {
+            Ok(immune) => immune,
+            Err(message) => return message,
+        };
+        let builder = TargetBuilder::from_action(&action).set_immune(&immune);
+        let builder = apply_target_options(builder, command);
+        match builder.build() {
+            Ok(target) => match self
+                .update_target(target_id, target.clone(), command.user.id)
+                .await
+            {
+                Ok(false) => format!("No target #{} in this server", target_id),
+                Ok(true) => {
+                    self.cache.invalidate(gid);
+                    format!("Target #{} updated", target_id)
+                }
+                Err(e) => {
+                    println!("Error updating target {:?}! {}", target, e);
+                    "Couldn't update the target, try again later".to_string()
+                }
+            },
+            Err(e) => build_error_message(e),
+        }
+    }
+
+    async fn update_target(
+        &self,
+        target_id: i64,
+        target: Target,
+        by: UserId,
+    ) -> store::StoreResult<bool> {
+        let now = SystemTime::now();
+        store::blocking(&self.store, move |s| {
+            s.update(target_id, &target, Some(by), now)
+        })
+        .await
+    }
+
+    /// Suggest rule IDs for the `id` option, matching the partially typed value
+    /// against either the ID or the rule summary.
+    async fn target_choices(&self, gid: GuildId, partial: &str) -> Vec<AutocompleteChoice> {
+        let now = SystemTime::now();
+        let targets = match store::blocking(&self.store, move |s| s.list(gid, now)).await {
+            Ok(targets) => targets,
+            Err(e) => {
+                println!("Error listing targets for {}: {}", gid, e);
+                return Vec::new();
+            }
+        };
+
+        targets
+            .iter()
+            .map(|action| (action.id, format!("#{} {}", action.id, action.summary())))
+            .filter(|(action_id, summary)| {
+                action_id.to_string().starts_with(partial) || summary.contains(partial)
+            })
+            .take(MAX_CHOICES)
+            .map(|(action_id, summary)| {
+                let name: String = summary.chars().take(MAX_CHOICE_LEN).collect();
+                AutocompleteChoice::new(name, action_id)
+            })
+            .collect()
+    }
+
+    /// Run a message through the rules as if `uid` had sent it, without reacting
+    /// or touching the letter chain.
+    async fn test_message(&self, gid: GuildId, uid: UserId, content: &str) -> String {
+        match self.cache.rules(gid).await {
+            Ok(rules) => {
+                let column = self.peek_column(content, gid, uid);
+                rules
+                    .evaluate(uid, content, column.as_deref(), SystemTime::now())
+                    .report()
+            }
+            Err(e) => {
+                println!("Error looking up targets for {}: {}", gid, e);
+                "Couldn't look up the targets, try again later".to_string()
+            }
+        }
+    }
+
+    /// Why the user isn't allowed to run this command, if they aren't.
+    fn refusal(&self, command: &CommandInteraction) -> Option<String> {
+        let access = Access::of(command);
+        // Outside of servers the commands explain themselves
+        let (Some(gid), Some(member)) = (command.guild_id, &command.member) else {
+            return None;
+        };
+        let settings = self.settings.guild(gid);
+        let permissions = member.permissions.unwrap_or_default();
+        match access {
+            Access::Anyone => None,
+            Access::Create if settings.may_create(&member.roles, permissions) => None,
+            Access::Manage if settings.may_manage(&member.roles, permissions) => None,
+            Access::Create => Some(format!(
+                "Only {} can add targets here",
+                role_mentions(&settings.creator_roles)
+            )),
+            Access::Manage if settings.manager_roles.is_empty() => {
+                Some("You need the Manage Messages permission to change targets".to_string())
+            }
+            Access::Manage => Some(format!(
+                "Only {} can change targets here",
+                role_mentions(&settings.manager_roles)
+            )),
+        }
+    }
+
+    /// Why the member running `command` can't add `target` yet, if one of the
+    /// guild's quotas or its cooldown stops them. Managers aren't limited.
+    async fn creation_limit(
+        &self,
+        command: &CommandInteraction,
+        target: &Target,
+    ) -> Option<String> {
+        let gid = target.get_guild();
+        let settings = self.settings.guild(gid);
+        let member = command.member.as_ref()?;
+        let permissions = member.permissions.unwrap_or_default();
+        if !settings.limits_creation() || settings.may_manage(&member.roles, permissions) {
+            return None;
+        }
+
+        let creator = command.user.id;
+        let now = SystemTime::now();
+        let cooldown = settings.creation_cooldown > 0;
+        let rules = store::blocking(&self.store, move |s| {
+            let last_created = if cooldown {
+                s.last_created(gid, creator)?
+            } else {
+                None
+            };
+            Ok((s.list(gid, now)?, last_created))
+        })
+        .await;
+        let (active, last_created) = match rules {
+            Ok(rules) => rules,
+            Err(e) => {
+                println!("Error checking targets by {}: {}", creator, e);
+                return Some("Couldn't check your targets, try again later".to_string());
+            }
+        };
+        let user = target.get_user().map(UserId::new);
+        settings
+            .creation_limit(creator, user, &active, last_created, now)
+            .map(|limit| limit.to_string())
+    }
+
+    /// Count refused reactions against their rules, and say so in the channel
+    /// when a rule gets paused for failing too often.
+    async fn reaction_failures(
+        &self,
+        ctx: &Context,
+        msg: &Message,
+        gid: GuildId,
+        failed: Vec<i64>,
+    ) {
+        let paused = match store::blocking(&self.store, move |s| {
+            s.record_failures(&failed, MAX_REACTION_FAILURES)
+        })
+        .await
+        {
+            Ok(paused) => paused,
+            Err(e) => {
+                println!("Error recording reaction failures: {}", e);
+                return;
+            }
+        };
+        if !paused.is_empty() {
+            self.cache.invalidate(gid);
+        }
+        for action in paused {
+            let content = format!(
+                "Paused target #{}: discord refused its emoji {} times in a row. Fix the \
+                emoji with /edit_target, then /resume_target",
+                action.id, action.failure_count
+            );
+            if let Err(why) = msg.channel_id.say(&ctx.http, content).await {
+                println!("Cannot announce paused target {}: {}", action.id, why);
+            }
+        }
+    }
+
+    /// The reply to a direct message: every rule in any server singling out `uid`.
+    async fn targets_on(&self, uid: UserId) -> String {
+        let now = SystemTime::now();
+        let targets = match store::blocking(&self.store, move |s| s.targeting_user(uid, now)).await
+        {
+            Ok(targets) => targets,
+            Err(e) => {
+                println!("Error looking up targets on {}: {}", uid, e);
+                return "Couldn't look up the targets, try again later".to_string();
+            }
+        };
+        if targets.is_empty() {
+            return "No targets on you right now".to_string();
+        }
+
+        let lines: Vec<String> = targets
+            .iter()
+            .map(|action| {
+                format!(
+                    "#{} in server {}: {}, expires in {}",
+                    action.id,
+                    action.guild_id,
+                    action.summary(),
+                    action.time_remaining(now)
+                )
+            })
+            .collect();
+        format!("Targets on you:\n{}", lines.join("\n"))
+    }
+
+    async fn direct_message(&self, ctx: &Context, msg: &Message) {
+        // Never answer bots, including our own replies
+        if !self.dm_commands || msg.author.bot {
+            return;
+        }
+        let content = self.targets_on(msg.author.id).await;
+        if let Err(why) = msg.channel_id.say(&ctx.http, content).await {
+            println!("Error answering direct message: {:?}", why);
+        }
+    }
+
+    /// What `check_column` would return for this message, without updating the chain.
+    fn peek_column(&self, msg: &str, gid: GuildId, uid: UserId) -> Option<String> {
+        let map = self.letter_chain();
+        let letters: Vec<&str> = msg.graphemes(true).collect();
+        match map.get(&gid) {
+            Some((user, s)) if letters.len() == 1 && *user == uid => Some(s.clone() + letters[0]),
+            _ => None,
         }
     }
 
@@ -63,7 +1030,7 @@ impl Handler {
         // letter_chain: Arc<Mutex<HashMap<GuildId, (UserId, String)>>>,
         // This is to attempt to handle cases where some loser tries to get around
         // our rules by typing letters out one at a time.
-        let mut map = self.letter_chain.lock().unwrap();
+        let mut map = self.letter_chain();
         let letters: Vec<&str> = msg.graphemes(true).collect();
 
         // Pop off the group's entry, if it exists
@@ -89,133 +1056,311 @@ impl Handler {
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
-        use crate::schema::actions::dsl::*;
-
+        let _running = self.in_flight.start();
         let time = SystemTime::now();
-        let gid = msg.guild_id.expect("No guild ID for message");
+        let Some(gid) = msg.guild_id else {
+            return self.direct_message(&ctx, &msg).await;
+        };
+        if self
+            .settings
+            .guild(gid)
+            .ignores(&msg, self.own_id.get().copied())
+        {
+            return;
+        }
         let uid = msg.author.id;
 
-        // Grab the results of the query and minimize the scope of the db lock
-        // Looking for either a matching or null author in the proper guild
-        let results = {
-            let mut db = self.db_con.lock().unwrap();
-            actions
-                .filter(guild_id.eq(gid.get() as i64))
-                .filter(user_id.eq(msg.author.id.get() as i64).or(user_id.is_null()))
-                .filter(expiration.is_null().or(expiration.gt(time)))
-                .load::<Action>(&mut *db)
-                .expect("Query Failed")
+        let rules = match self.cache.rules(gid).await {
+            Ok(rules) => rules,
+            Err(e) => {
+                println!("Error looking up targets for {}: {}", gid, e);
+                return;
+            }
         };
 
         let column = self.check_column(&msg.content, gid, uid);
 
         // Gather all the reactions. If there is a regex, check it, if not then
-        // just add the reaction
-        let mut reaction_set = ReactionSet::new();
-        for action in results {
-            if let Some(s) = action.regex {
-                // There is a regex, so see if it matches
-                let r = Regex::new(&s).unwrap();
-                if r.is_match(&msg.content) {
-                    reaction_set.add_reactions(&action.reactions);
-                }
+        // just add the reaction.
+        let evaluation = rules.evaluate(uid, &msg.content, column.as_deref(), time);
+        if evaluation.column_matched {
+            self.letter_chain().remove(&gid);
+        }
 
-                if let Some(s) = &column {
-                    if r.is_match(s) {
-                        reaction_set.add_reactions(&action.reactions);
-                        self.letter_chain.lock().unwrap().remove(&gid);
+        // Go through each rule's reactions and react to the message,
+        // remembering which rules had an emoji refused
+        let mut fired = Vec::new();
+        let mut failed = Vec::new();
+        for (rule, reactions) in &evaluation.by_rule {
+            let mut refused = false;
+            for reaction in reactions {
+                match reaction.parse::<Emoji>() {
+                    Ok(emoji) => {
+                        if let Err(why) =
+                            msg.react(ctx.http.clone(), emoji.to_reaction_type()).await
+                        {
+                            println!("Error reacting to message: {:?}", why);
+                            refused |= unknown_emoji(&why);
+                        }
+                    }
+                    Err(e) => {
+                        println!("Skipping stored reaction: {}", e);
+                        refused = true;
                     }
                 }
+            }
+            if refused {
+                failed.push(*rule);
             } else {
-                // No regex, so just add to list if not conflicting
-                reaction_set.add_reactions(&action.reactions);
+                fired.push(*rule);
             }
         }
 
-        // Go through all the reactions and react to the message appropriately
-        for reaction in reaction_set.as_list() {
-            if let Ok(r) = ReactionType::try_from(reaction.as_str()) {
-                if let Err(why) = msg.react(ctx.http.clone(), r).await {
-                    println!("Error reacting to message: {:?}", why);
-                }
+        // Keep track of which rules actually got to react
+        if !fired.is_empty() {
+            if let Err(e) = store::blocking(&self.store, move |s| s.record_hits(&fired, time)).await
+            {
+                println!("Error recording target hits: {}", e);
             }
         }
-
-        // Delete any expired rules
-        {
-            let mut db = self.db_con.lock().unwrap();
-            diesel::delete(actions.filter(expiration.lt(time)))
-                .execute(&mut *db)
-                .expect("Delete failed");
+        if !failed.is_empty() {
+            self.reaction_failures(&ctx, &msg, gid, failed).await;
         }
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
-        if let Interaction::Command(command) = interaction {
-            let content = match command.data.name.as_str() {
-                "target_user" | "target_regex" => {
-                    let mut builder = Target::builder();
-                    builder = builder.set_guild(command.guild_id.unwrap());
-                    for entry in &command.data.options {
-                        match entry.name.as_ref() {
-                            "emotes" => {
-                                if let CommandDataOptionValue::String(s) = &entry.value {
-                                    builder = builder.set_emotes(s)
+        match interaction {
+            Interaction::Command(command) => {
+                let response = if let Some(refusal) = self.refusal(&command) {
+                    CreateInteractionResponseMessage::new()
+                        .content(refusal)
+                        .ephemeral(true)
+                } else {
+                    match command.data.name.as_str() {
+                        "target_user" | "target_regex" => {
+                            let (content, limited) = self.add_target(&command).await;
+                            CreateInteractionResponseMessage::new()
+                                .content(content)
+                                .ephemeral(limited)
+                        }
+                        "breadbot" => {
+                            let content = match (command.guild_id, subcommand(&command)) {
+                                (Some(gid), Some((choice @ ("optout" | "optin"), _))) => {
+                                    self.opt_out(gid, command.user.id, choice == "optout").await
                                 }
-                            }
-                            "duration" => {
-                                if let CommandDataOptionValue::Integer(int) = &entry.value {
-                                    builder = builder.set_expiration(*int as u64)
+                                (None, _) => "Targets only exist in servers".to_string(),
+                                _ => "not implemented :(".to_string(),
+                            };
+                            CreateInteractionResponseMessage::new()
+                                .content(content)
+                                .ephemeral(true)
+                        }
+                        "immunity" => {
+                            let content = match (command.guild_id, subcommand(&command)) {
+                                (Some(gid), Some((action, options))) => {
+                                    self.immunity(gid, command.user.id, action, options).await
                                 }
-                            }
-                            "user" => {
-                                if let CommandDataOptionValue::User(user) = &entry.value {
-                                    builder = builder.set_user(*user)
+                                (None, _) => "Targets only exist in servers".to_string(),
+                                (_, None) => "Need add, remove or list".to_string(),
+                            };
+                            CreateInteractionResponseMessage::new()
+                                .content(content)
+                                .ephemeral(true)
+                        }
+                        "edit_target" => {
+                            let content = match (command.guild_id, integer_option(&command, "id")) {
+                                (Some(gid), Some(target_id)) => {
+                                    self.edit_target(gid, target_id, &command).await
                                 }
-                            }
-                            "regex" => {
-                                if let CommandDataOptionValue::String(s) = &entry.value {
-                                    builder = builder.set_regex(s)
+                                (None, _) => "Targets only exist in servers".to_string(),
+                                (_, None) => "Need the ID of the target to edit".to_string(),
+                            };
+                            CreateInteractionResponseMessage::new().content(content)
+                        }
+                        "list_targets" => match command.guild_id {
+                            Some(gid) => self.list_targets(gid, 0).await,
+                            None => CreateInteractionResponseMessage::new()
+                                .content("Targets only exist in servers"),
+                        },
+                        "pause_target" | "resume_target" => {
+                            let state = command.data.name == "resume_target";
+                            let verb = if state { "resumed" } else { "paused" };
+                            let content = match (command.guild_id, integer_option(&command, "id")) {
+                                (Some(gid), Some(target_id)) => {
+                                    match store::blocking(&self.store, move |s| {
+                                        s.set_enabled(gid, target_id, state)
+                                    })
+                                    .await
+                                    {
+                                        Ok(false) => {
+                                            format!("No target #{} in this server", target_id)
+                                        }
+                                        Ok(true) => {
+                                            self.cache.invalidate(gid);
+                                            format!("Target #{} {}", target_id, verb)
+                                        }
+                                        Err(e) => {
+                                            println!("Error updating target {}: {}", target_id, e);
+                                            "Couldn't update the target, try again later"
+                                                .to_string()
+                                        }
+                                    }
                                 }
-                            }
-                            _ => println!("Unexpected entry name: {}", entry.name),
+                                (None, _) => "Targets only exist in servers".to_string(),
+                                (_, None) => "Need the ID of the target".to_string(),
+                            };
+                            CreateInteractionResponseMessage::new().content(content)
                         }
-                    }
-                    match builder.build() {
-                        Ok(target) => {
-                            self.target(target);
-                            "Target added".to_string()
+                        "test_message" => {
+                            let text = string_option(&command, "text");
+                            let user = user_option(&command, "user").unwrap_or(command.user.id);
+                            let content = match (command.guild_id, text) {
+                                (Some(gid), Some(text)) => self.test_message(gid, user, text).await,
+                                (None, _) => "Targets only exist in servers".to_string(),
+                                (_, None) => "Need some text to test".to_string(),
+                            };
+                            CreateInteractionResponseMessage::new()
+                                .content(content)
+                                .ephemeral(true)
+                        }
+                        "remove_target" => {
+                            let by = command.user.id;
+                            let now = SystemTime::now();
+                            let content = match (command.guild_id, integer_option(&command, "id")) {
+                                (Some(gid), Some(target_id)) => {
+                                    match store::blocking(&self.store, move |s| {
+                                        s.remove(gid, target_id, Some(by), now)
+                                    })
+                                    .await
+                                    {
+                                        Ok(false) => {
+                                            format!("No target #{} in this server", target_id)
+                                        }
+                                        Ok(true) => {
+                                            self.cache.invalidate(gid);
+                                            format!("Target #{} removed", target_id)
+                                        }
+                                        Err(e) => {
+                                            println!("Error removing target {}: {}", target_id, e);
+                                            "Couldn't remove the target, try again later"
+                                                .to_string()
+                                        }
+                                    }
+                                }
+                                (None, _) => "Targets only exist in servers".to_string(),
+                                (_, None) => "Need the ID of the target to remove".to_string(),
+                            };
+                            CreateInteractionResponseMessage::new().content(content)
                         }
-                        Err(TargetBuilderError::BadRegex(_)) => {
-                            "Your regex game is weak, bitch. Refer to \
-                            https://docs.rs/regex/latest/regex/index.html#syntax"
-                                .to_string()
+                        "export_targets" => {
+                            let format = string_option(&command, "format")
+                                .and_then(|f| f.parse().ok())
+                                .unwrap_or(Format::Toml);
+                            match command.guild_id {
+                                Some(gid) => self.export_targets(gid, format).await,
+                                None => CreateInteractionResponseMessage::new()
+                                    .content("Targets only exist in servers"),
+                            }
                         }
-                        Err(TargetBuilderError::MissingUserAndRegex) => {
-                            "Need either a user or a regex or both... bitch".to_string()
+                        "import_targets" => {
+                            let options = ImportOptions {
+                                guild: command.guild_id,
+                                skip_expired: bool_option(&command, "skip_expired")
+                                    .unwrap_or(false),
+                                allow_empty_match: bool_option(&command, "allow_empty")
+                                    .unwrap_or(false),
+                                creator: Some(command.user.id),
+                            };
+                            let content = match attachment_option(&command, "file") {
+                                Some(file) => self.import_targets(file, options).await,
+                                None => "Need a file to import".to_string(),
+                            };
+                            CreateInteractionResponseMessage::new().content(content)
                         }
-                        Err(e) => e.to_string(),
+                        "target_history" => {
+                            let restore = integer_option(&command, "restore");
+                            let duration = integer_option(&command, "duration");
+                            match (command.guild_id, restore) {
+                                (Some(gid), Some(archive_id)) => {
+                                    let content = self
+                                        .restore_target(gid, archive_id, duration, command.user.id)
+                                        .await;
+                                    CreateInteractionResponseMessage::new().content(content)
+                                }
+                                (Some(gid), None) => self.target_history(gid, 0).await,
+                                (None, _) => CreateInteractionResponseMessage::new()
+                                    .content("Targets only exist in servers"),
+                            }
+                        }
+                        _ => CreateInteractionResponseMessage::new().content("not implemented :("),
                     }
-                }
-                _ => "not implemented :(".to_string(),
-            };
+                };
 
-            if let Err(why) = command
-                .create_response(&ctx.http, {
-                    let response = CreateInteractionResponseMessage::new().content(content);
-                    CreateInteractionResponse::Message(response)
-                })
-                .await
-            {
-                println!("Cannot respond to slash command: {}", why);
+                if let Err(why) = command
+                    .create_response(&ctx.http, CreateInteractionResponse::Message(response))
+                    .await
+                {
+                    println!("Cannot respond to slash command: {}", why);
+                }
+            }
+            Interaction::Autocomplete(command) => {
+                let choices = match (command.guild_id, command.data.autocomplete()) {
+                    (Some(gid), Some(option)) if option.name == "id" => {
+                        self.target_choices(gid, option.value).await
+                    }
+                    _ => Vec::new(),
+                };
+                let response = CreateAutocompleteResponse::new().set_choices(choices);
+                if let Err(why) = command
+                    .create_response(&ctx.http, CreateInteractionResponse::Autocomplete(response))
+                    .await
+                {
+                    println!("Cannot respond to autocomplete: {}", why);
+                }
             }
+            Interaction::Component(component) => {
+                // Page buttons carry the listing and where the page starts in their ID
+                let page = component
+                    .data
+                    .custom_id
+                    .split_once(':')
+                    .and_then(|(list, p)| Some((list, p.parse::<usize>().ok()?)));
+                if let (Some((list, start)), Some(gid)) = (page, component.guild_id) {
+                    let response = match list {
+                        "list_targets" => self.list_targets(gid, start).await,
+                        "target_history" => self.target_history(gid, start).await,
+                        _ => return,
+                    };
+                    if let Err(why) = component
+                        .create_response(
+                            &ctx.http,
+                            CreateInteractionResponse::UpdateMessage(response),
+                        )
+                        .await
+                    {
+                        println!("Cannot update target list: {}", why);
+                    }
+                }
+            }
+            _ => {}
         }
     }
 
     async fn ready(&self, ctx: Context, ready: Ready) {
+        let _ = self.own_id.set(ready.user.id);
+
         // Commands for all servers
         let mut commands = Vec::new();
+        let allow_empty_option = || {
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "allow_empty",
+                "Accept a regex that matches every message",
+            )
+            .required(false)
+        };
         let command = CreateCommand::new("target_user")
+            .default_member_permissions(Permissions::SEND_MESSAGES)
             .description("Target a user")
             .add_option({
                 CreateCommandOption::new(CommandOptionType::User, "user", "The user to target")
@@ -245,11 +1390,14 @@ impl EventHandler for Handler {
                     "regex",
                     "Regular expression to match against",
                 )
+                .max_length(MAX_PATTERN_LEN as u16)
                 .required(false)
-            });
+            })
+            .add_option(allow_empty_option());
         commands.push(command);
 
         let command = CreateCommand::new("target_regex")
+            .default_member_permissions(Permissions::SEND_MESSAGES)
             .description("Target message content")
             .add_option({
                 CreateCommandOption::new(
@@ -257,8 +1405,10 @@ impl EventHandler for Handler {
                     "regex",
                     "Regular expression to match against",
                 )
+                .max_length(MAX_PATTERN_LEN as u16)
                 .required(true)
             })
+            .add_option(allow_empty_option())
             .add_option({
                 CreateCommandOption::new(
                     CommandOptionType::String,
@@ -283,9 +1433,296 @@ impl EventHandler for Handler {
             });
         commands.push(command);
 
+        let command = CreateCommand::new("list_targets").description("List active targets");
+        commands.push(command);
+
+        let command = CreateCommand::new("edit_target")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .description("Edit an existing target")
+            .add_option({
+                CreateCommandOption::new(CommandOptionType::Integer, "id", "ID of the target")
+                    .set_autocomplete(true)
+                    .required(true)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "emotes",
+                    "List of emotes to target with",
+                )
+                .required(false)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "duration",
+                    "New length of target in minutes from now",
+                )
+                .min_int_value(1)
+                .max_int_value(1440)
+                .required(false)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "regex",
+                    "Regular expression to match against",
+                )
+                .max_length(MAX_PATTERN_LEN as u16)
+                .required(false)
+            })
+            .add_option(allow_empty_option())
+            .add_option({
+                CreateCommandOption::new(CommandOptionType::User, "user", "The user to target")
+                    .required(false)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "clear_user",
+                    "Target everyone instead of a single user",
+                )
+                .required(false)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "clear_regex",
+                    "Match every message instead of a regex",
+                )
+                .required(false)
+            });
+        commands.push(command);
+
+        let command = CreateCommand::new("pause_target")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .description("Stop a target from firing without removing it")
+            .add_option({
+                CreateCommandOption::new(CommandOptionType::Integer, "id", "ID of the target")
+                    .set_autocomplete(true)
+                    .required(true)
+            });
+        commands.push(command);
+
+        let command = CreateCommand::new("resume_target")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .description("Resume a paused target")
+            .add_option({
+                CreateCommandOption::new(CommandOptionType::Integer, "id", "ID of the target")
+                    .set_autocomplete(true)
+                    .required(true)
+            });
+        commands.push(command);
+
+        let command = CreateCommand::new("test_message")
+            .description("Show which targets would fire for a message")
+            .add_option({
+                CreateCommandOption::new(CommandOptionType::String, "text", "Message to test")
+                    .required(true)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::User,
+                    "user",
+                    "Author of the message, defaults to you",
+                )
+                .required(false)
+            });
+        commands.push(command);
+
+        let command = CreateCommand::new("remove_target")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .description("Remove a target")
+            .add_option({
+                CreateCommandOption::new(CommandOptionType::Integer, "id", "ID of the target")
+                    .set_autocomplete(true)
+                    .required(true)
+            });
+        commands.push(command);
+
+        let command = CreateCommand::new("export_targets")
+            .description("Download this server's active targets")
+            .add_option({
+                CreateCommandOption::new(CommandOptionType::String, "format", "File format")
+                    .add_string_choice("TOML", "toml")
+                    .add_string_choice("JSON", "json")
+                    .required(false)
+            });
+        commands.push(command);
+
+        let command = CreateCommand::new("import_targets")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .description("Add targets from a file made by /export_targets")
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::Attachment,
+                    "file",
+                    "A .toml or .json rule export",
+                )
+                .required(true)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::Boolean,
+                    "skip_expired",
+                    "Leave out targets that have already expired",
+                )
+                .required(false)
+            })
+            .add_option(allow_empty_option());
+        commands.push(command);
+
+        let command = CreateCommand::new("target_history")
+            .description("Browse expired, removed and edited targets, or restore one")
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "restore",
+                    "Archive ID of a target to restore",
+                )
+                .required(false)
+            })
+            .add_option({
+                CreateCommandOption::new(
+                    CommandOptionType::Integer,
+                    "duration",
+                    "Length of the restored target in minutes",
+                )
+                .min_int_value(1)
+                .max_int_value(1440)
+                .required(false)
+            });
+        commands.push(command);
+
+        let command = CreateCommand::new("breadbot")
+            .description("Choose whether targets can single you out")
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "optout",
+                "Become immune to targets in this server",
+            ))
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "optin",
+                "Allow targets to single you out again",
+            ));
+        commands.push(command);
+
+        let user_option = || {
+            CreateCommandOption::new(CommandOptionType::User, "user", "The member").required(true)
+        };
+        let command = CreateCommand::new("immunity")
+            .default_member_permissions(Permissions::MANAGE_MESSAGES)
+            .description("Manage who is immune to targets")
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "add",
+                    "Make a member immune to targets",
+                )
+                .add_sub_option(user_option()),
+            )
+            .add_option(
+                CreateCommandOption::new(
+                    CommandOptionType::SubCommand,
+                    "remove",
+                    "Allow targets against a member again",
+                )
+                .add_sub_option(user_option()),
+            )
+            .add_option(CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "list",
+                "Show who is immune to targets",
+            ));
+        commands.push(command);
+
         Command::set_global_commands(&ctx.http, commands)
             .await
             .unwrap();
         println!("{} is connected!", ready.user.name);
+        systemd::notify_ready();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+
+    fn handler_with(targets: &[(Option<u64>, Option<&str>, &str)]) -> Handler {
+        let store = MemoryStore::new();
+        for (user, regex, emotes) in targets {
+            let mut builder = Target::builder()
+                .set_guild(GuildId::new(1))
+                .set_emotes(emotes);
+            if let Some(user) = user {
+                builder = builder.set_user(UserId::new(*user));
+            }
+            if let Some(regex) = regex {
+                builder = builder.set_regex(regex);
+            }
+            store
+                .insert(&builder.build().unwrap(), SystemTime::now())
+                .unwrap();
+        }
+        Handler::new(Arc::new(store), Duration::from_secs(60))
+    }
+
+    #[test]
+    fn pages_fit_embed_limit() {
+        let field = |n: usize| (format!("#{}", n), "🍞".repeat(1000));
+        let fields: Vec<_> = (0..LIST_PAGE_SIZE).map(field).collect();
+        let page = fit_page(fields, 5900);
+        assert_eq!(page.len(), 5);
+        let page = fit_page(vec![(String::new(), "🍞".repeat(7000))], 10);
+        assert_eq!(page[0].1.chars().count(), MAX_FIELD_LEN);
+
+        let short: Vec<_> = (0..20).map(|n| (n.to_string(), "a".to_string())).collect();
+        assert_eq!(fit_page(short, 5900).len(), LIST_PAGE_SIZE);
+    }
+
+    #[test]
+    fn long_fields_truncated() {
+        assert_eq!(truncate("bread".to_string(), MAX_FIELD_LEN), "bread");
+        let long = truncate("🍞".repeat(2000), MAX_FIELD_LEN);
+        assert_eq!(long.chars().count(), MAX_FIELD_LEN);
+        assert!(long.ends_with('…'));
+    }
+
+    #[tokio::test]
+    async fn test_message_reports() {
+        let handler = handler_with(&[(None, Some("bread"), "🍞"), (Some(5), None, "🍞🥖")]);
+        let report = handler
+            .test_message(GuildId::new(1), UserId::new(5), "bread")
+            .await;
+        assert_eq!(
+            report,
+            "Matched: #1, #2\nDropped for overlapping emoji: #2\nReactions: 🍞"
+        );
+    }
+
+    #[tokio::test]
+    async fn targets_on_user() {
+        let handler = handler_with(&[(None, Some("bread"), "🍞"), (Some(5), None, "🥖")]);
+        assert_eq!(
+            handler.targets_on(UserId::new(5)).await,
+            "Targets on you:\n#2 in server 1: @5 🥖, expires in never"
+        );
+        assert_eq!(
+            handler.targets_on(UserId::new(6)).await,
+            "No targets on you right now"
+        );
+    }
+
+    #[test]
+    fn column_chain() {
+        let handler = handler_with(&[]);
+        let (gid, uid) = (GuildId::new(1), UserId::new(5));
+        assert_eq!(handler.check_column("b", gid, uid), None);
+        assert_eq!(handler.peek_column("r", gid, uid), Some("br".to_string()));
+        assert_eq!(handler.check_column("r", gid, uid), Some("br".to_string()));
+        assert_eq!(handler.check_column("e", gid, UserId::new(6)), None);
+        assert_eq!(handler.peek_column("e", gid, uid), None);
     }
 }
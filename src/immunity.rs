@@ -0,0 +1,44 @@
+use crate::action::discord_timestamp;
+use crate::schema::immune_users;
+use diesel::{Insertable, Queryable};
+use std::time::SystemTime;
+
+/// A member that rules can't single out, and whose messages are left alone.
+#[derive(Queryable, Insertable, Debug, Clone, PartialEq, Eq)]
+#[diesel(table_name = immune_users)]
+pub struct Immunity {
+    pub guild_id: i64,
+    pub user_id: i64,
+    /// Who made them immune, either themselves or a moderator.
+    pub added_by: Option<i64>,
+    pub added_at: SystemTime,
+}
+
+impl Immunity {
+    /// Whether the member opted out themselves, and so may opt back in.
+    pub fn self_added(&self) -> bool {
+        self.added_by == Some(self.user_id)
+    }
+
+    /// One line description, e.g. "<@1234> opted out 3 days ago".
+    pub fn describe(&self) -> String {
+        match self.added_by {
+            _ if self.self_added() => format!(
+                "<@{}> opted out {}",
+                self.user_id,
+                discord_timestamp(self.added_at)
+            ),
+            Some(by) => format!(
+                "<@{}> added by <@{}> {}",
+                self.user_id,
+                by,
+                discord_timestamp(self.added_at)
+            ),
+            None => format!(
+                "<@{}> added {}",
+                self.user_id,
+                discord_timestamp(self.added_at)
+            ),
+        }
+    }
+}
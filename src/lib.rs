@@ -2,7 +2,17 @@
 extern crate diesel;
 pub mod action;
 pub mod config;
+pub mod emoji;
+pub mod evaluation;
+pub mod export;
 pub mod handler;
+pub mod immunity;
 pub mod reaction_set;
+pub mod rule_cache;
 pub mod schema;
+pub mod settings;
+pub mod shutdown;
+pub mod store;
+pub mod sweeper;
+pub mod systemd;
 pub mod target;
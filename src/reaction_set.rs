@@ -1,5 +1,8 @@
 use std::collections::BTreeSet;
-use unicode_segmentation::UnicodeSegmentation;
+
+/// Discord allows this many distinct reactions on a message.
+pub const MAX_REACTIONS: usize = 20;
+
 pub struct ReactionSet {
     list: Vec<String>,
     set: BTreeSet<String>,
@@ -13,21 +16,24 @@ impl ReactionSet {
         }
     }
 
-    pub fn add_reactions(&mut self, reactions: &[String]) {
-        let single_grapheme = reactions.concat().graphemes(true).count() == 1;
-        let mut map = if single_grapheme {
-            BTreeSet::from([reactions.concat()])
-        } else {
-            reactions.iter().map(|x| x.to_owned()).collect()
-        };
+    /// Whether a message has room for all of `reactions` on top of the ones
+    /// already added.
+    pub fn has_room(&self, reactions: &[String]) -> bool {
+        let new: BTreeSet<&String> = reactions.iter().collect();
+        self.set.len() + new.len() <= MAX_REACTIONS
+    }
 
-        if self.set.is_disjoint(&map) {
+    /// Add a group of reactions, unless any of them are already in the set.
+    /// Returns whether the reactions were added.
+    pub fn add_reactions(&mut self, reactions: &[String]) -> bool {
+        let mut map: BTreeSet<String> = reactions.iter().map(|x| x.to_owned()).collect();
+
+        if self.set.is_disjoint(&map) && self.has_room(reactions) {
             self.set.append(&mut map);
-            if single_grapheme {
-                self.list.push(reactions.concat());
-            } else {
-                self.list.extend_from_slice(reactions);
-            }
+            self.list.extend_from_slice(reactions);
+            true
+        } else {
+            false
         }
     }
 
@@ -63,8 +69,28 @@ mod tests {
     #[test]
     fn no_duplicates() {
         let mut set = ReactionSet::new();
-        set.add_reactions(&["a".to_string()]);
-        set.add_reactions(&["a".to_string()]);
+        assert!(set.add_reactions(&["a".to_string()]));
+        assert!(!set.add_reactions(&["a".to_string()]));
         assert_eq!(set.as_list().concat(), "a");
     }
+
+    #[test]
+    fn limited_room() {
+        let mut set = ReactionSet::new();
+        let full: Vec<String> = (0..18).map(|i| i.to_string()).collect();
+        assert!(set.add_reactions(&full));
+        assert!(!set.has_room(&["a".to_string(), "b".to_string(), "c".to_string()]));
+        assert!(!set.add_reactions(&["a".to_string(), "b".to_string(), "c".to_string()]));
+        assert!(set.add_reactions(&["a".to_string(), "b".to_string()]));
+        assert!(!set.has_room(&["c".to_string()]));
+        assert_eq!(set.as_list().len(), MAX_REACTIONS);
+    }
+
+    #[test]
+    fn entries_kept_whole() {
+        let mut set = ReactionSet::new();
+        set.add_reactions(&["🇧".to_string(), "🇷".to_string()]);
+        set.add_reactions(&["🇺🇸".to_string()]);
+        assert_eq!(set.as_list(), ["🇧", "🇷", "🇺🇸"]);
+    }
 }
@@ -0,0 +1,156 @@
+use crate::evaluation::CompiledRules;
+use crate::store::{self, RuleStore, StoreResult};
+use serenity::model::id::{GuildId, UserId};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Compiled rules for each guild, along with who is immune to them, so that
+/// messages can be checked without a trip to the database. Changes made
+/// through the bot invalidate the guild's entry straight away, and entries are
+/// reloaded after `refresh` to pick up rules added from the command line.
+pub struct RuleCache {
+    store: Arc<dyn RuleStore>,
+    refresh: Duration,
+    guilds: Mutex<HashMap<GuildId, Entry>>,
+}
+
+#[derive(Default)]
+struct Entry {
+    rules: Option<(Instant, Arc<CompiledRules>)>,
+    /// Bumped by every invalidation, so a load that started before one doesn't
+    /// put back rules read before the change.
+    generation: u64,
+    /// Held while the guild is loaded, so that messages arriving together
+    /// don't each load it.
+    loading: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl RuleCache {
+    pub fn new(store: Arc<dyn RuleStore>, refresh: Duration) -> Self {
+        Self {
+            store,
+            refresh,
+            guilds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn guilds(&self) -> MutexGuard<'_, HashMap<GuildId, Entry>> {
+        self.guilds.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The guild's rules if they're cached and fresh.
+    fn cached(&self, gid: GuildId) -> Option<Arc<CompiledRules>> {
+        match &self.guilds().get(&gid)?.rules {
+            Some((loaded, rules)) if loaded.elapsed() < self.refresh => Some(rules.clone()),
+            _ => None,
+        }
+    }
+
+    fn generation(&self, gid: GuildId) -> u64 {
+        self.guilds().get(&gid).map_or(0, |e| e.generation)
+    }
+
+    /// Cache rules loaded at `generation`, unless the guild was invalidated
+    /// since. Returns whether they were cached.
+    fn fill(&self, gid: GuildId, generation: u64, rules: Arc<CompiledRules>) -> bool {
+        let mut guilds = self.guilds();
+        let entry = guilds.entry(gid).or_default();
+        if entry.generation != generation {
+            return false;
+        }
+        entry.rules = Some((Instant::now(), rules));
+        true
+    }
+
+    /// The rules for a guild, loading them from the store if they aren't
+    /// cached or are older than the refresh interval.
+    pub async fn rules(&self, gid: GuildId) -> StoreResult<Arc<CompiledRules>> {
+        if let Some(rules) = self.cached(gid) {
+            return Ok(rules);
+        }
+
+        let loading = self.guilds().entry(gid).or_default().loading.clone();
+        let _loading = loading.lock().await;
+        // Someone else may have loaded the guild while we waited
+        if let Some(rules) = self.cached(gid) {
+            return Ok(rules);
+        }
+
+        let generation = self.generation(gid);
+        let now = SystemTime::now();
+        let (actions, immune) = store::blocking(&self.store, move |s| {
+            Ok((s.list(gid, now)?, s.immune_users(gid)?))
+        })
+        .await?;
+        let immune = immune.iter().map(|i| UserId::new(i.user_id as u64));
+        let rules = Arc::new(CompiledRules::new(actions).with_immune(immune));
+        self.fill(gid, generation, rules.clone());
+        Ok(rules)
+    }
+
+    /// Drop a guild's rules so the next message reloads them.
+    pub fn invalidate(&self, gid: GuildId) {
+        let mut guilds = self.guilds();
+        let entry = guilds.entry(gid).or_default();
+        entry.generation += 1;
+        entry.rules = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::target::Target;
+
+    #[tokio::test]
+    async fn invalidate_reloads() {
+        let store = Arc::new(MemoryStore::new());
+        let cache = RuleCache::new(store.clone(), Duration::from_secs(3600));
+        let gid = GuildId::new(1);
+        let target = Target::builder()
+            .set_guild(gid)
+            .set_emotes("🍞")
+            .set_regex("bread")
+            .build()
+            .unwrap();
+        let now = SystemTime::now();
+
+        let rules = cache.rules(gid).await.unwrap();
+        assert!(rules
+            .evaluate(UserId::new(5), "bread", None, now)
+            .matched
+            .is_empty());
+
+        // Still cached until invalidated
+        store.insert(&target, SystemTime::now()).unwrap();
+        let rules = cache.rules(gid).await.unwrap();
+        assert!(rules
+            .evaluate(UserId::new(5), "bread", None, now)
+            .matched
+            .is_empty());
+
+        cache.invalidate(gid);
+        let rules = cache.rules(gid).await.unwrap();
+        assert_eq!(
+            rules.evaluate(UserId::new(5), "bread", None, now).matched,
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn invalidate_during_load() {
+        let cache = RuleCache::new(Arc::new(MemoryStore::new()), Duration::from_secs(3600));
+        let gid = GuildId::new(1);
+        let stale = Arc::new(CompiledRules::new(Vec::new()));
+
+        let generation = cache.generation(gid);
+        cache.invalidate(gid);
+        assert!(!cache.fill(gid, generation, stale.clone()));
+        assert!(cache.cached(gid).is_none());
+
+        assert!(cache.fill(gid, cache.generation(gid), stale));
+        assert!(cache.cached(gid).is_some());
+    }
+}
@@ -4,7 +4,42 @@ table! {
         guild_id -> Int8,
         user_id -> Nullable<Int8>,
         regex -> Nullable<Text>,
-        reactions -> Array<Bpchar>,
+        reactions -> Array<Text>,
         expiration -> Nullable<Timestamp>,
+        enabled -> Bool,
+        created_by -> Nullable<Int8>,
+        created_at -> Nullable<Timestamp>,
+        hit_count -> Int8,
+        last_fired_at -> Nullable<Timestamp>,
+        failure_count -> Int8,
+    }
+}
+
+table! {
+    archived_actions (archive_id) {
+        archive_id -> Int8,
+        action_id -> Int8,
+        guild_id -> Int8,
+        user_id -> Nullable<Int8>,
+        regex -> Nullable<Text>,
+        reactions -> Array<Text>,
+        expiration -> Nullable<Timestamp>,
+        enabled -> Bool,
+        created_by -> Nullable<Int8>,
+        created_at -> Nullable<Timestamp>,
+        hit_count -> Int8,
+        last_fired_at -> Nullable<Timestamp>,
+        reason -> Text,
+        archived_by -> Nullable<Int8>,
+        archived_at -> Timestamp,
+    }
+}
+
+table! {
+    immune_users (guild_id, user_id) {
+        guild_id -> Int8,
+        user_id -> Int8,
+        added_by -> Nullable<Int8>,
+        added_at -> Timestamp,
     }
 }
@@ -0,0 +1,333 @@
+use crate::action::{discord_timestamp, Action};
+use serde::Deserialize;
+use serenity::model::channel::{Message, MessageType};
+use serenity::model::id::{GuildId, RoleId, UserId};
+use serenity::model::permissions::Permissions;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+/// Per-guild behaviour, from a `[[guild]]` entry in the config. Fields left
+/// out take the same defaults as guilds without an entry.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuildSettings {
+    pub id: u64,
+    /// Skip messages from bot accounts.
+    #[serde(default = "default_true")]
+    pub ignore_bots: bool,
+    /// Skip messages posted through webhooks.
+    #[serde(default = "default_true")]
+    pub ignore_webhooks: bool,
+    /// Skip joins, pins, boosts and the other messages discord posts itself.
+    #[serde(default = "default_true")]
+    pub ignore_system: bool,
+    /// Bots whose messages are checked even with `ignore_bots`.
+    #[serde(default)]
+    pub allowed_bots: Vec<u64>,
+    /// Roles that may add targets. Anyone may when empty.
+    #[serde(default)]
+    pub creator_roles: Vec<u64>,
+    /// Roles that may edit, pause, remove, restore and import targets. When
+    /// empty, members with the Manage Messages permission may.
+    #[serde(default)]
+    pub manager_roles: Vec<u64>,
+    /// Most active targets one member may have added. No limit when left out.
+    #[serde(default)]
+    pub max_rules_per_creator: Option<usize>,
+    /// Most active targets singling out one user. No limit when left out.
+    #[serde(default)]
+    pub max_rules_per_user: Option<usize>,
+    /// Seconds a member has to wait between adding targets.
+    #[serde(default)]
+    pub creation_cooldown: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl GuildSettings {
+    /// The settings for a guild without an entry in the config.
+    pub fn new(gid: GuildId) -> Self {
+        GuildSettings {
+            id: gid.get(),
+            ignore_bots: true,
+            ignore_webhooks: true,
+            ignore_system: true,
+            allowed_bots: Vec::new(),
+            creator_roles: Vec::new(),
+            manager_roles: Vec::new(),
+            max_rules_per_creator: None,
+            max_rules_per_user: None,
+            creation_cooldown: 0,
+        }
+    }
+
+    /// Whether a member with these roles and permissions may manage targets.
+    /// Administrators always may.
+    pub fn may_manage(&self, roles: &[RoleId], permissions: Permissions) -> bool {
+        if permissions.administrator() {
+            return true;
+        }
+        if self.manager_roles.is_empty() {
+            return permissions.manage_messages();
+        }
+        roles.iter().any(|r| self.manager_roles.contains(&r.get()))
+    }
+
+    /// Whether a member may add targets. Anyone who may manage them may.
+    pub fn may_create(&self, roles: &[RoleId], permissions: Permissions) -> bool {
+        self.creator_roles.is_empty()
+            || roles.iter().any(|r| self.creator_roles.contains(&r.get()))
+            || self.may_manage(roles, permissions)
+    }
+
+    /// Whether any quota or cooldown applies to adding targets.
+    pub fn limits_creation(&self) -> bool {
+        self.max_rules_per_creator.is_some()
+            || self.max_rules_per_user.is_some()
+            || self.creation_cooldown > 0
+    }
+
+    /// Why `creator` can't add a target against `user` yet, if they can't.
+    /// `active` holds the guild's live rules and `last_created` when
+    /// `creator` last added one, including rules since removed.
+    pub fn creation_limit(
+        &self,
+        creator: UserId,
+        user: Option<UserId>,
+        active: &[Action],
+        last_created: Option<SystemTime>,
+        now: SystemTime,
+    ) -> Option<CreationLimit> {
+        if let Some(max) = self.max_rules_per_creator {
+            let created = active
+                .iter()
+                .filter(|a| a.created_by == Some(creator.get() as i64));
+            if let Some(retry) = quota(max, created) {
+                return Some(CreationLimit::Creator(max, retry));
+            }
+        }
+        if let (Some(max), Some(user)) = (self.max_rules_per_user, user) {
+            let against = active
+                .iter()
+                .filter(|a| a.user_id == Some(user.get() as i64));
+            if let Some(retry) = quota(max, against) {
+                return Some(CreationLimit::Target(max, retry));
+            }
+        }
+        let ready = last_created? + Duration::from_secs(self.creation_cooldown);
+        (ready > now).then_some(CreationLimit::Cooldown(ready))
+    }
+
+    /// Whether rules should be skipped for this message. The bot's own
+    /// messages, `own_id`, are always skipped.
+    pub fn ignores(&self, msg: &Message, own_id: Option<UserId>) -> bool {
+        if own_id == Some(msg.author.id) {
+            return true;
+        }
+        if self.ignore_webhooks && msg.webhook_id.is_some() {
+            return true;
+        }
+        if self.ignore_system
+            && !matches!(msg.kind, MessageType::Regular | MessageType::InlineReply)
+        {
+            return true;
+        }
+        self.ignore_bots && msg.author.bot && !self.allowed_bots.contains(&msg.author.id.get())
+    }
+}
+
+/// If `rules` already reach `max`, when enough of them expire to make room
+/// for another, or `None` inside if that takes removing some.
+fn quota<'a>(max: usize, rules: impl Iterator<Item = &'a Action>) -> Option<Option<SystemTime>> {
+    let rules: Vec<&Action> = rules.collect();
+    if rules.len() < max {
+        return None;
+    }
+    let mut expirations: Vec<SystemTime> = rules.iter().filter_map(|a| a.expiration).collect();
+    expirations.sort();
+    Some(expirations.get(rules.len() - max).copied())
+}
+
+/// Why a member can't add another target yet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CreationLimit {
+    /// They already have the most active targets allowed, until the given
+    /// time if one expires.
+    Creator(usize, Option<SystemTime>),
+    /// The user already has the most targets against them allowed.
+    Target(usize, Option<SystemTime>),
+    /// They added a target too recently, and can again at the given time.
+    Cooldown(SystemTime),
+}
+
+impl fmt::Display for CreationLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let retry = |time: &Option<SystemTime>| match time {
+            Some(time) => format!("try again {}", discord_timestamp(*time)),
+            None => "try again once one is removed".to_string(),
+        };
+        match &self {
+            CreationLimit::Creator(max, time) => write!(
+                f,
+                "You already have {} active targets here, {}",
+                max,
+                retry(time)
+            ),
+            CreationLimit::Target(max, time) => write!(
+                f,
+                "That user already has {} targets against them, {}",
+                max,
+                retry(time)
+            ),
+            CreationLimit::Cooldown(time) => write!(
+                f,
+                "You're adding targets too quickly, try again {}",
+                discord_timestamp(*time)
+            ),
+        }
+    }
+}
+
+/// Settings for every guild, falling back to the defaults for guilds that
+/// aren't configured.
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    guilds: HashMap<GuildId, GuildSettings>,
+}
+
+impl Settings {
+    pub fn new(guilds: Vec<GuildSettings>) -> Self {
+        Settings {
+            guilds: guilds
+                .into_iter()
+                .map(|g| (GuildId::new(g.id), g))
+                .collect(),
+        }
+    }
+
+    pub fn guild(&self, gid: GuildId) -> GuildSettings {
+        self.guilds
+            .get(&gid)
+            .cloned()
+            .unwrap_or_else(|| GuildSettings::new(gid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::model::id::WebhookId;
+
+    fn message(author: u64, bot: bool) -> Message {
+        let mut msg = Message::default();
+        msg.author.id = UserId::new(author);
+        msg.author.bot = bot;
+        msg
+    }
+
+    #[test]
+    fn roles() {
+        let mut settings = GuildSettings::new(GuildId::new(1));
+        let none = Permissions::empty();
+        let member = [RoleId::new(10)];
+        assert!(settings.may_create(&member, none));
+        assert!(!settings.may_manage(&member, none));
+        assert!(settings.may_manage(&[], Permissions::MANAGE_MESSAGES));
+
+        settings.creator_roles.push(20);
+        settings.manager_roles.push(30);
+        assert!(!settings.may_create(&member, none));
+        assert!(settings.may_create(&[RoleId::new(20)], none));
+        assert!(settings.may_create(&[RoleId::new(30)], none));
+        assert!(!settings.may_manage(&[RoleId::new(20)], Permissions::MANAGE_MESSAGES));
+        assert!(settings.may_manage(&member, Permissions::ADMINISTRATOR));
+    }
+
+    fn rule(creator: i64, user: i64, expiration: Option<SystemTime>) -> Action {
+        Action {
+            id: 0,
+            guild_id: 1,
+            user_id: Some(user),
+            regex: None,
+            reactions: vec!["🍞".to_string()],
+            expiration,
+            enabled: true,
+            created_by: Some(creator),
+            created_at: None,
+            hit_count: 0,
+            last_fired_at: None,
+            failure_count: 0,
+        }
+    }
+
+    #[test]
+    fn creation_limits() {
+        let now = SystemTime::now();
+        let soon = now + Duration::from_secs(60);
+        let later = now + Duration::from_secs(600);
+        let (creator, user) = (UserId::new(2), Some(UserId::new(3)));
+        let active = [
+            rule(2, 3, Some(later)),
+            rule(2, 4, Some(soon)),
+            rule(5, 3, None),
+        ];
+        let mut settings = GuildSettings::new(GuildId::new(1));
+        assert_eq!(
+            settings.creation_limit(creator, user, &active, Some(now), now),
+            None
+        );
+
+        settings.max_rules_per_creator = Some(2);
+        assert_eq!(
+            settings.creation_limit(creator, user, &active, None, now),
+            Some(CreationLimit::Creator(2, Some(soon)))
+        );
+        settings.max_rules_per_creator = Some(3);
+        settings.max_rules_per_user = Some(1);
+        assert_eq!(
+            settings.creation_limit(creator, user, &active, None, now),
+            Some(CreationLimit::Target(1, None))
+        );
+        assert_eq!(
+            settings.creation_limit(creator, None, &active, None, now),
+            None
+        );
+
+        settings.creation_cooldown = 60;
+        assert_eq!(
+            settings.creation_limit(creator, None, &active, Some(now), now),
+            Some(CreationLimit::Cooldown(soon))
+        );
+        assert_eq!(
+            settings.creation_limit(creator, None, &active, Some(now), soon),
+            None
+        );
+    }
+
+    #[test]
+    fn ignored_messages() {
+        let own = Some(UserId::new(1));
+        let mut settings = GuildSettings::new(GuildId::new(1));
+        assert!(!settings.ignores(&message(2, false), own));
+        assert!(settings.ignores(&message(1, true), own));
+        assert!(settings.ignores(&message(3, true), own));
+
+        let mut hooked = message(2, false);
+        hooked.webhook_id = Some(WebhookId::new(4));
+        assert!(settings.ignores(&hooked, own));
+
+        let mut pinned = message(2, false);
+        pinned.kind = MessageType::PinsAdd;
+        assert!(settings.ignores(&pinned, own));
+
+        settings.allowed_bots.push(3);
+        assert!(!settings.ignores(&message(3, true), own));
+        assert!(settings.ignores(&message(5, true), own));
+
+        settings.ignore_bots = false;
+        assert!(!settings.ignores(&message(5, true), own));
+        assert!(settings.ignores(&message(1, true), own));
+    }
+}
@@ -0,0 +1,542 @@
+use super::{RuleStore, StoreResult};
+use crate::action::{Action, ArchiveReason, ArchivedAction};
+use crate::immunity::Immunity;
+use crate::target::Target;
+use serenity::model::id::{GuildId, UserId};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+/// Rule storage that only lives as long as the process. Useful for tests and
+/// for trying the bot out without a database.
+#[derive(Default)]
+pub struct MemoryStore {
+    rules: Mutex<Vec<Action>>,
+    archive: Mutex<Vec<ArchivedAction>>,
+    immune: Mutex<Vec<Immunity>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A panic elsewhere doesn't leave the rules half written, so carry on
+    /// through a poisoned lock.
+    fn rules(&self) -> MutexGuard<'_, Vec<Action>> {
+        self.rules.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn archive(&self) -> MutexGuard<'_, Vec<ArchivedAction>> {
+        self.archive.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn immune(&self) -> MutexGuard<'_, Vec<Immunity>> {
+        self.immune.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn archive_rules(
+        &self,
+        rules: &[Action],
+        reason: ArchiveReason,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) {
+        let mut archive = self.archive();
+        for action in rules {
+            let archive_id = archive.len() as i64 + 1;
+            archive.push(ArchivedAction {
+                archive_id,
+                action: action.clone(),
+                reason,
+                archived_by: by.map(|u| u.get() as i64),
+                archived_at: time,
+            });
+        }
+    }
+}
+
+fn is_live(action: &Action, time: SystemTime) -> bool {
+    action.expiration.is_none_or(|e| e > time)
+}
+
+impl RuleStore for MemoryStore {
+    fn insert(&self, target: &Target, time: SystemTime) -> StoreResult<i64> {
+        Ok(self.insert_all(&[(target.clone(), true)], time)?[0])
+    }
+
+    fn insert_all(&self, targets: &[(Target, bool)], time: SystemTime) -> StoreResult<Vec<i64>> {
+        let mut rules = self.rules();
+        // Don't hand out IDs that are still referenced from the archive
+        let archived = self.archive().iter().map(|a| a.action.id).max();
+        let mut id = rules.iter().map(|a| a.id).max().max(archived).unwrap_or(0);
+        let mut ids = Vec::new();
+        for (target, enabled) in targets {
+            id += 1;
+            rules.push(Action {
+                id,
+                guild_id: target.get_guild().get() as i64,
+                user_id: target.get_user().map(|x| x as i64),
+                regex: target.get_regex().cloned(),
+                reactions: target.get_emotes().to_vec(),
+                expiration: target.get_expiration(),
+                enabled: *enabled,
+                created_by: target.get_creator().map(|x| x as i64),
+                created_at: Some(time),
+                hit_count: 0,
+                last_fired_at: None,
+                failure_count: 0,
+            });
+            ids.push(id);
+        }
+        Ok(ids)
+    }
+
+    fn active_for_message(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>> {
+        let rules = self.rules();
+        Ok(rules
+            .iter()
+            .filter(|a| a.guild_id == gid.get() as i64)
+            .filter(|a| a.user_id.is_none_or(|u| u == uid.get() as i64))
+            .filter(|a| is_live(a, time) && a.enabled)
+            .cloned()
+            .collect())
+    }
+
+    fn list(&self, gid: GuildId, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let rules = self.rules();
+        Ok(rules
+            .iter()
+            .filter(|a| a.guild_id == gid.get() as i64 && is_live(a, time))
+            .cloned()
+            .collect())
+    }
+
+    fn targeting_user(&self, uid: UserId, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let rules = self.rules();
+        Ok(rules
+            .iter()
+            .filter(|a| a.user_id == Some(uid.get() as i64) && is_live(a, time))
+            .cloned()
+            .collect())
+    }
+
+    fn get(&self, gid: GuildId, id: i64) -> StoreResult<Option<Action>> {
+        let rules = self.rules();
+        Ok(rules
+            .iter()
+            .find(|a| a.id == id && a.guild_id == gid.get() as i64)
+            .cloned())
+    }
+
+    fn update(
+        &self,
+        id: i64,
+        target: &Target,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut rules = self.rules();
+        let gid = target.get_guild().get() as i64;
+        match rules.iter_mut().find(|a| a.id == id && a.guild_id == gid) {
+            Some(action) => {
+                self.archive_rules(
+                    std::slice::from_ref(action),
+                    ArchiveReason::Edited,
+                    by,
+                    time,
+                );
+                action.user_id = target.get_user().map(|x| x as i64);
+                action.regex = target.get_regex().cloned();
+                action.reactions = target.get_emotes().to_vec();
+                action.expiration = target.get_expiration();
+                action.failure_count = 0;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn set_enabled(&self, gid: GuildId, id: i64, enabled: bool) -> StoreResult<bool> {
+        let mut rules = self.rules();
+        let gid = gid.get() as i64;
+        match rules.iter_mut().find(|a| a.id == id && a.guild_id == gid) {
+            Some(action) => {
+                action.enabled = enabled;
+                if enabled {
+                    action.failure_count = 0;
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn set_expiration(
+        &self,
+        gid: GuildId,
+        id: i64,
+        expires: Option<SystemTime>,
+    ) -> StoreResult<bool> {
+        let mut rules = self.rules();
+        let gid = gid.get() as i64;
+        match rules.iter_mut().find(|a| a.id == id && a.guild_id == gid) {
+            Some(action) => {
+                action.expiration = expires;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn remove(
+        &self,
+        gid: GuildId,
+        id: i64,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut rules = self.rules();
+        let (removed, kept) = rules
+            .drain(..)
+            .partition::<Vec<_>, _>(|a| a.id == id && a.guild_id == gid.get() as i64);
+        *rules = kept;
+        self.archive_rules(&removed, ArchiveReason::Removed, by, time);
+        Ok(!removed.is_empty())
+    }
+
+    fn purge(
+        &self,
+        gid: Option<GuildId>,
+        uid: Option<UserId>,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>> {
+        let mut rules = self.rules();
+        let (removed, kept) = rules.drain(..).partition::<Vec<_>, _>(|a| {
+            gid.is_none_or(|g| a.guild_id == g.get() as i64)
+                && uid.is_none_or(|u| a.user_id == Some(u.get() as i64))
+        });
+        *rules = kept;
+        self.archive_rules(&removed, ArchiveReason::Removed, by, time);
+        Ok(removed)
+    }
+
+    fn record_hits(&self, ids: &[i64], time: SystemTime) -> StoreResult<()> {
+        let mut rules = self.rules();
+        for action in rules.iter_mut().filter(|a| ids.contains(&a.id)) {
+            action.hit_count += 1;
+            action.last_fired_at = Some(time);
+            action.failure_count = 0;
+        }
+        Ok(())
+    }
+
+    fn record_failures(&self, ids: &[i64], limit: i64) -> StoreResult<Vec<Action>> {
+        let mut rules = self.rules();
+        let mut paused = Vec::new();
+        for action in rules.iter_mut().filter(|a| ids.contains(&a.id)) {
+            action.failure_count += 1;
+            if action.enabled && action.failure_count >= limit {
+                action.enabled = false;
+                paused.push(action.clone());
+            }
+        }
+        Ok(paused)
+    }
+
+    fn delete_expired(&self, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let mut rules = self.rules();
+        let (live, expired) = rules
+            .drain(..)
+            .partition(|a| a.expiration.is_none_or(|e| e >= time));
+        *rules = live;
+        self.archive_rules(&expired, ArchiveReason::Expired, None, time);
+        Ok(expired)
+    }
+
+    fn last_created(&self, gid: GuildId, creator: UserId) -> StoreResult<Option<SystemTime>> {
+        let rules = self.rules();
+        let archive = self.archive();
+        Ok(rules
+            .iter()
+            .chain(archive.iter().map(|a| &a.action))
+            .filter(|a| a.guild_id == gid.get() as i64)
+            .filter(|a| a.created_by == Some(creator.get() as i64))
+            .filter_map(|a| a.created_at)
+            .max())
+    }
+
+    fn history(
+        &self,
+        gid: GuildId,
+        offset: usize,
+        limit: usize,
+    ) -> StoreResult<Vec<ArchivedAction>> {
+        let archive = self.archive();
+        Ok(archive
+            .iter()
+            .rev()
+            .filter(|a| a.action.guild_id == gid.get() as i64)
+            .skip(offset)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn history_len(&self, gid: GuildId) -> StoreResult<usize> {
+        let archive = self.archive();
+        Ok(archive
+            .iter()
+            .filter(|a| a.action.guild_id == gid.get() as i64)
+            .count())
+    }
+
+    fn get_archived(&self, gid: GuildId, archive_id: i64) -> StoreResult<Option<ArchivedAction>> {
+        let archive = self.archive();
+        Ok(archive
+            .iter()
+            .find(|a| a.archive_id == archive_id && a.action.guild_id == gid.get() as i64)
+            .cloned())
+    }
+
+    fn add_immunity(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let (gid, uid) = (gid.get() as i64, uid.get() as i64);
+        let mut immune = self.immune();
+        if immune.iter().any(|i| i.guild_id == gid && i.user_id == uid) {
+            return Ok(false);
+        }
+        immune.push(Immunity {
+            guild_id: gid,
+            user_id: uid,
+            added_by: by.map(|u| u.get() as i64),
+            added_at: time,
+        });
+        Ok(true)
+    }
+
+    fn remove_immunity(&self, gid: GuildId, uid: UserId) -> StoreResult<bool> {
+        let mut immune = self.immune();
+        let before = immune.len();
+        immune.retain(|i| !(i.guild_id == gid.get() as i64 && i.user_id == uid.get() as i64));
+        Ok(immune.len() < before)
+    }
+
+    fn immune_users(&self, gid: GuildId) -> StoreResult<Vec<Immunity>> {
+        let immune = self.immune();
+        Ok(immune
+            .iter()
+            .filter(|i| i.guild_id == gid.get() as i64)
+            .cloned()
+            .collect())
+    }
+
+    fn migrate(&self) -> StoreResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target::TargetBuilder;
+    use std::time::Duration;
+
+    fn target(gid: u64, user: Option<u64>, regex: Option<&str>) -> Target {
+        let mut builder = Target::builder()
+            .set_guild(GuildId::new(gid))
+            .set_emotes("🍞");
+        if let Some(user) = user {
+            builder = builder.set_user(UserId::new(user));
+        }
+        if let Some(regex) = regex {
+            builder = builder.set_regex(regex);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn scoped_to_guild_and_user() {
+        let store = MemoryStore::new();
+        let anyone = store
+            .insert(&target(1, None, Some("bread")), SystemTime::now())
+            .unwrap();
+        let user = store
+            .insert(&target(1, Some(5), None), SystemTime::now())
+            .unwrap();
+        store
+            .insert(&target(1, Some(6), None), SystemTime::now())
+            .unwrap();
+        store
+            .insert(&target(2, None, Some("bread")), SystemTime::now())
+            .unwrap();
+
+        let now = SystemTime::now();
+        let ids: Vec<i64> = store
+            .active_for_message(GuildId::new(1), UserId::new(5), now)
+            .unwrap()
+            .iter()
+            .map(|a| a.id)
+            .collect();
+        assert_eq!(ids, vec![anyone, user]);
+        assert_eq!(store.list(GuildId::new(1), now).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn remove_checks_guild() {
+        let store = MemoryStore::new();
+        let id = store
+            .insert(&target(1, Some(5), None), SystemTime::now())
+            .unwrap();
+        let now = SystemTime::now();
+        assert!(!store.remove(GuildId::new(2), id, None, now).unwrap());
+        assert!(store.remove(GuildId::new(1), id, None, now).unwrap());
+        assert!(store.get(GuildId::new(1), id).unwrap().is_none());
+    }
+
+    #[test]
+    fn history_and_restore() {
+        let store = MemoryStore::new();
+        let now = SystemTime::now();
+        let id = store.insert(&target(1, Some(5), None), now).unwrap();
+        let edited = target(1, Some(6), None);
+        let editor = Some(UserId::new(9));
+        assert!(store.update(id, &edited, editor, now).unwrap());
+        assert!(store.remove(GuildId::new(1), id, editor, now).unwrap());
+
+        assert_eq!(store.history_len(GuildId::new(1)).unwrap(), 2);
+        let history = store.history(GuildId::new(1), 0, 10).unwrap();
+        let reasons: Vec<ArchiveReason> = history.iter().map(|a| a.reason).collect();
+        assert_eq!(reasons, [ArchiveReason::Removed, ArchiveReason::Edited]);
+        assert_eq!(history[1].action.user_id, Some(5));
+        assert_eq!(history[0].archived_by, Some(9));
+        assert!(store.history(GuildId::new(2), 0, 10).unwrap().is_empty());
+        let page = store.history(GuildId::new(1), 1, 10).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].archive_id, history[1].archive_id);
+        assert_eq!(store.history(GuildId::new(1), 0, 1).unwrap().len(), 1);
+
+        let archived = store
+            .get_archived(GuildId::new(1), history[1].archive_id)
+            .unwrap()
+            .unwrap();
+        let restored = TargetBuilder::from_action(&archived.action)
+            .build()
+            .unwrap();
+        let new_id = store.insert(&restored, now).unwrap();
+        assert_ne!(new_id, id);
+        assert_eq!(
+            store.list(GuildId::new(1), now).unwrap()[0].user_id,
+            Some(5)
+        );
+    }
+
+    #[test]
+    fn immunity() {
+        let store = MemoryStore::new();
+        let (gid, uid) = (GuildId::new(1), UserId::new(5));
+        let now = SystemTime::now();
+        assert!(store.add_immunity(gid, uid, Some(uid), now).unwrap());
+        assert!(!store.add_immunity(gid, uid, None, now).unwrap());
+        assert!(store.immune_users(GuildId::new(2)).unwrap().is_empty());
+
+        let immune = store.immune_users(gid).unwrap();
+        assert_eq!(immune.len(), 1);
+        assert!(immune[0].self_added());
+
+        let refused = Target::builder()
+            .set_guild(gid)
+            .set_user(uid)
+            .set_emotes("🍞")
+            .set_immune(&[uid])
+            .build();
+        assert!(matches!(
+            refused,
+            Err(crate::target::TargetBuilderError::ImmuneUser(5))
+        ));
+
+        assert!(store.remove_immunity(gid, uid).unwrap());
+        assert!(!store.remove_immunity(gid, uid).unwrap());
+    }
+
+    #[test]
+    fn failures_pause_rule() {
+        let store = MemoryStore::new();
+        let gid = GuildId::new(1);
+        let now = SystemTime::now();
+        let id = store.insert(&target(1, None, Some("bread")), now).unwrap();
+
+        assert!(store.record_failures(&[id], 2).unwrap().is_empty());
+        store.record_hits(&[id], now).unwrap();
+        assert!(store.record_failures(&[id], 2).unwrap().is_empty());
+        let paused = store.record_failures(&[id], 2).unwrap();
+        assert_eq!(paused.len(), 1);
+        assert!(!paused[0].enabled);
+        assert!(!store.get(gid, id).unwrap().unwrap().enabled);
+        assert!(store.record_failures(&[id], 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn edit_and_resume_reset_failures() {
+        let store = MemoryStore::new();
+        let gid = GuildId::new(1);
+        let now = SystemTime::now();
+        let id = store.insert(&target(1, None, Some("bread")), now).unwrap();
+
+        store.record_failures(&[id], 2).unwrap();
+        store.record_failures(&[id], 2).unwrap();
+        store.set_enabled(gid, id, true).unwrap();
+        let resumed = store.get(gid, id).unwrap().unwrap();
+        assert!(resumed.enabled);
+        assert_eq!(resumed.failure_count, 0);
+
+        store.record_failures(&[id], 2).unwrap();
+        store.set_enabled(gid, id, false).unwrap();
+        assert_eq!(store.get(gid, id).unwrap().unwrap().failure_count, 1);
+
+        store
+            .update(id, &target(1, None, Some("toast")), None, now)
+            .unwrap();
+        assert_eq!(store.get(gid, id).unwrap().unwrap().failure_count, 0);
+    }
+
+    #[test]
+    fn disabled_and_expired_skipped() {
+        let store = MemoryStore::new();
+        let id = store
+            .insert(&target(1, Some(5), None), SystemTime::now())
+            .unwrap();
+        store
+            .insert(&target(1, Some(5), None), SystemTime::now())
+            .unwrap();
+        store.set_enabled(GuildId::new(1), id, false).unwrap();
+
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(3600);
+        let expiring = Target::builder()
+            .set_guild(GuildId::new(1))
+            .set_emotes("🥖")
+            .set_user(UserId::new(5))
+            .set_expiration(1)
+            .build()
+            .unwrap();
+        store.insert(&expiring, SystemTime::now()).unwrap();
+
+        let active = store
+            .active_for_message(GuildId::new(1), UserId::new(5), now)
+            .unwrap();
+        assert_eq!(active.len(), 2);
+        assert_eq!(store.delete_expired(later).unwrap().len(), 1);
+        assert_eq!(store.list(GuildId::new(1), now).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::action::{Action, ArchivedAction};
+use crate::immunity::Immunity;
+use crate::target::Target;
+use diesel::backend::Backend;
+use diesel::migration::MigrationSource;
+use diesel_migrations::{EmbeddedMigrations, MigrationHarness};
+use serenity::model::id::{GuildId, UserId};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+mod memory;
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+pub use memory::MemoryStore;
+pub use postgres::PgStore;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+#[derive(Debug)]
+pub enum StoreError {
+    Database(diesel::result::Error),
+    Connection(diesel::ConnectionError),
+    Pool(diesel::r2d2::PoolError),
+    Blocking(tokio::task::JoinError),
+    Migration(Box<dyn Error + Send + Sync>),
+    /// The database has migrations applied that this binary doesn't know about.
+    SchemaTooNew(Vec<String>),
+    UnsupportedUrl(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            StoreError::Database(e) => write!(f, "Database error: {}", e),
+            StoreError::Connection(e) => write!(f, "Connection error: {}", e),
+            StoreError::Pool(e) => write!(f, "Connection pool error: {}", e),
+            StoreError::Blocking(e) => write!(f, "Database task failed: {}", e),
+            StoreError::Migration(e) => write!(f, "Migration error: {}", e),
+            StoreError::SchemaTooNew(versions) => write!(
+                f,
+                "Database schema is newer than this binary, unknown migrations: {}",
+                versions.join(", ")
+            ),
+            StoreError::UnsupportedUrl(url) => write!(f, "No rule store for {}", url),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<diesel::result::Error> for StoreError {
+    fn from(e: diesel::result::Error) -> Self {
+        StoreError::Database(e)
+    }
+}
+
+impl From<diesel::ConnectionError> for StoreError {
+    fn from(e: diesel::ConnectionError) -> Self {
+        StoreError::Connection(e)
+    }
+}
+
+impl From<diesel::r2d2::PoolError> for StoreError {
+    fn from(e: diesel::r2d2::PoolError) -> Self {
+        StoreError::Pool(e)
+    }
+}
+
+impl From<tokio::task::JoinError> for StoreError {
+    fn from(e: tokio::task::JoinError) -> Self {
+        StoreError::Blocking(e)
+    }
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+/// Storage for the reaction rules. Methods that take a guild only touch rules
+/// in that guild, and report whether a matching rule was found.
+pub trait RuleStore: Send + Sync {
+    /// Add a new rule created at `time`, returning its ID.
+    fn insert(&self, target: &Target, time: SystemTime) -> StoreResult<i64>;
+
+    /// Add several rules created at `time`, each enabled or paused as given,
+    /// returning their IDs. Either all of them are added or none are.
+    fn insert_all(&self, targets: &[(Target, bool)], time: SystemTime) -> StoreResult<Vec<i64>>;
+
+    /// Enabled, unexpired rules in a guild that apply to messages from the
+    /// given user, either because they target that user or anyone.
+    fn active_for_message(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>>;
+
+    /// All unexpired rules for a guild, oldest first.
+    fn list(&self, gid: GuildId, time: SystemTime) -> StoreResult<Vec<Action>>;
+
+    /// Unexpired rules in any guild that single out `uid`, oldest first.
+    fn targeting_user(&self, uid: UserId, time: SystemTime) -> StoreResult<Vec<Action>>;
+
+    fn get(&self, gid: GuildId, id: i64) -> StoreResult<Option<Action>>;
+
+    /// Overwrite a rule with the contents of `target`, within the target's
+    /// guild. The previous version is archived as edited by `by` at `time`.
+    fn update(
+        &self,
+        id: i64,
+        target: &Target,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool>;
+
+    fn set_enabled(&self, gid: GuildId, id: i64, enabled: bool) -> StoreResult<bool>;
+
+    /// Change when a rule expires, or make it permanent with `None`.
+    fn set_expiration(
+        &self,
+        gid: GuildId,
+        id: i64,
+        expires: Option<SystemTime>,
+    ) -> StoreResult<bool>;
+
+    /// Move a rule to the archive as removed by `by` at `time`.
+    fn remove(
+        &self,
+        gid: GuildId,
+        id: i64,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool>;
+
+    /// Move every rule in `gid` and/or targeting `uid` to the archive as
+    /// removed by `by` at `time`, returning the removed rules. With neither
+    /// filter every rule is removed.
+    fn purge(
+        &self,
+        gid: Option<GuildId>,
+        uid: Option<UserId>,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>>;
+
+    /// Count a firing of each of the rules, as of `time`, clearing their
+    /// failures.
+    fn record_hits(&self, ids: &[i64], time: SystemTime) -> StoreResult<()>;
+
+    /// Count a reaction that discord refused for an unknown emoji against each
+    /// of the rules, pausing any that have now failed `limit` times in a row.
+    /// Returns the rules that were paused.
+    fn record_failures(&self, ids: &[i64], limit: i64) -> StoreResult<Vec<Action>>;
+
+    /// Move every rule that expired before `time` to the archive, returning
+    /// the removed rules.
+    fn delete_expired(&self, time: SystemTime) -> StoreResult<Vec<Action>>;
+
+    /// When `creator` last created a rule in a guild, including rules that
+    /// have since been archived.
+    fn last_created(&self, gid: GuildId, creator: UserId) -> StoreResult<Option<SystemTime>>;
+
+    /// Up to `limit` archived rules for a guild, most recently archived first,
+    /// skipping the first `offset`.
+    fn history(
+        &self,
+        gid: GuildId,
+        offset: usize,
+        limit: usize,
+    ) -> StoreResult<Vec<ArchivedAction>>;
+
+    /// How many archived rules a guild has.
+    fn history_len(&self, gid: GuildId) -> StoreResult<usize>;
+
+    fn get_archived(&self, gid: GuildId, archive_id: i64) -> StoreResult<Option<ArchivedAction>>;
+
+    /// Make `uid` immune to rules in `gid`, as requested by `by` at `time`.
+    /// Reports false if they already were.
+    fn add_immunity(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool>;
+
+    fn remove_immunity(&self, gid: GuildId, uid: UserId) -> StoreResult<bool>;
+
+    /// Members of a guild that are immune to rules, oldest first.
+    fn immune_users(&self, gid: GuildId) -> StoreResult<Vec<Immunity>>;
+
+    /// Bring the schema up to date, returning the versions of the migrations
+    /// that were applied.
+    fn migrate(&self) -> StoreResult<Vec<String>>;
+}
+
+/// Apply the pending embedded migrations, refusing to touch a database that
+/// has migrations newer than the ones built into this binary.
+fn run_migrations<DB: Backend>(
+    harness: &mut impl MigrationHarness<DB>,
+    source: EmbeddedMigrations,
+) -> StoreResult<Vec<String>> {
+    let known: HashSet<String> = MigrationSource::<DB>::migrations(&source)
+        .map_err(StoreError::Migration)?
+        .iter()
+        .map(|m| m.name().version().to_string())
+        .collect();
+    let unknown: Vec<String> = harness
+        .applied_migrations()
+        .map_err(StoreError::Migration)?
+        .iter()
+        .map(|v| v.to_string())
+        .filter(|v| !known.contains(v))
+        .collect();
+    if !unknown.is_empty() {
+        return Err(StoreError::SchemaTooNew(unknown));
+    }
+
+    let applied = harness
+        .run_pending_migrations(source)
+        .map_err(StoreError::Migration)?;
+    Ok(applied.iter().map(|v| v.to_string()).collect())
+}
+
+/// Run blocking store operations on tokio's blocking thread pool, so that
+/// database I/O doesn't stall the async workers. A panic in `f` comes back as
+/// an error.
+pub async fn blocking<T, F>(store: &Arc<dyn RuleStore>, f: F) -> StoreResult<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&dyn RuleStore) -> StoreResult<T> + Send + 'static,
+{
+    let store = store.clone();
+    tokio::task::spawn_blocking(move || f(&*store)).await?
+}
+
+/// Open the rule store for a database URL, picking the backend from the
+/// scheme: `postgres://`, `sqlite://<path>` (with the `sqlite` feature), or
+/// `memory:` to keep the rules in the process. Database backends keep a pool
+/// of up to `pool_size` connections.
+pub fn connect(url: &str, pool_size: u32) -> StoreResult<Arc<dyn RuleStore>> {
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        return Ok(Arc::new(PgStore::establish(url, pool_size)?));
+    }
+    if url.starts_with("memory:") {
+        return Ok(Arc::new(MemoryStore::new()));
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = url.strip_prefix("sqlite://") {
+        return Ok(Arc::new(SqliteStore::establish(path, pool_size)?));
+    }
+    Err(StoreError::UnsupportedUrl(url.to_string()))
+}
@@ -0,0 +1,462 @@
+use super::{run_migrations, RuleStore, StoreError, StoreResult};
+use crate::action::{Action, ArchiveReason, ArchivedAction};
+use crate::emoji::regroup_legacy;
+use crate::immunity::Immunity;
+use crate::schema::actions::dsl::*;
+use crate::schema::archived_actions as archive;
+use crate::schema::immune_users as immune;
+use crate::target::Target;
+use diesel::insert_into;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use serenity::model::id::{GuildId, UserId};
+use std::time::SystemTime;
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// The migration that moved reactions from single characters to whole emoji.
+const STRUCTURED_REACTIONS: &str = "20261018000100";
+
+/// Rejoin the emoji that rules from before `STRUCTURED_REACTIONS` hold split
+/// into single codepoints.
+fn regroup_legacy_reactions(db: &mut PgConnection) -> StoreResult<()> {
+    db.transaction::<_, StoreError, _>(|db| {
+        let rows: Vec<(i64, Vec<String>)> = actions.select((id, reactions)).load(db)?;
+        for (rule, stored) in rows {
+            let regrouped = regroup_legacy(&stored);
+            if regrouped != stored {
+                diesel::update(actions.filter(id.eq(rule)))
+                    .set(reactions.eq(regrouped))
+                    .execute(db)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[derive(Queryable)]
+struct ArchiveRow {
+    archive_id: i64,
+    action_id: i64,
+    guild_id: i64,
+    user_id: Option<i64>,
+    regex: Option<String>,
+    reactions: Vec<String>,
+    expiration: Option<SystemTime>,
+    enabled: bool,
+    created_by: Option<i64>,
+    created_at: Option<SystemTime>,
+    hit_count: i64,
+    last_fired_at: Option<SystemTime>,
+    reason: String,
+    archived_by: Option<i64>,
+    archived_at: SystemTime,
+}
+
+impl From<ArchiveRow> for ArchivedAction {
+    fn from(row: ArchiveRow) -> Self {
+        ArchivedAction {
+            archive_id: row.archive_id,
+            action: Action {
+                id: row.action_id,
+                guild_id: row.guild_id,
+                user_id: row.user_id,
+                regex: row.regex,
+                reactions: row.reactions,
+                expiration: row.expiration,
+                enabled: row.enabled,
+                created_by: row.created_by,
+                created_at: row.created_at,
+                hit_count: row.hit_count,
+                last_fired_at: row.last_fired_at,
+                failure_count: 0,
+            },
+            // The column is constrained to the known reasons
+            reason: row.reason.parse().unwrap_or(ArchiveReason::Removed),
+            archived_by: row.archived_by,
+            archived_at: row.archived_at,
+        }
+    }
+}
+
+/// Copy rules into the archive.
+fn archive_rules(
+    db: &mut PgConnection,
+    rules: &[Action],
+    reason: ArchiveReason,
+    by: Option<UserId>,
+    time: SystemTime,
+) -> QueryResult<usize> {
+    let rows: Vec<_> = rules
+        .iter()
+        .map(|a| {
+            (
+                archive::action_id.eq(a.id),
+                archive::guild_id.eq(a.guild_id),
+                archive::user_id.eq(a.user_id),
+                archive::regex.eq(a.regex.clone()),
+                archive::reactions.eq(a.reactions.clone()),
+                archive::expiration.eq(a.expiration),
+                archive::enabled.eq(a.enabled),
+                archive::created_by.eq(a.created_by),
+                archive::created_at.eq(a.created_at),
+                archive::hit_count.eq(a.hit_count),
+                archive::last_fired_at.eq(a.last_fired_at),
+                archive::reason.eq(reason.as_str()),
+                archive::archived_by.eq(by.map(|u| u.get() as i64)),
+                archive::archived_at.eq(time),
+            )
+        })
+        .collect();
+    insert_into(archive::table).values(rows).execute(db)
+}
+
+pub struct PgStore {
+    pool: Pool<ConnectionManager<PgConnection>>,
+}
+
+impl PgStore {
+    pub fn new(pool: Pool<ConnectionManager<PgConnection>>) -> Self {
+        Self { pool }
+    }
+
+    /// Connect to postgres with a pool of up to `pool_size` connections.
+    pub fn establish(url: &str, pool_size: u32) -> StoreResult<Self> {
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .build(ConnectionManager::new(url))?;
+        Ok(Self::new(pool))
+    }
+}
+
+/// Add a rule, enabled or paused, returning its ID.
+fn insert_rule(
+    db: &mut PgConnection,
+    target: &Target,
+    on: bool,
+    time: SystemTime,
+) -> StoreResult<i64> {
+    Ok(insert_into(actions)
+        .values((
+            guild_id.eq(target.get_guild().get() as i64),
+            user_id.eq(target.get_user().map(|x| x as i64)),
+            reactions.eq(target.get_emotes()),
+            expiration.eq(target.get_expiration()),
+            regex.eq(target.get_regex()),
+            enabled.eq(on),
+            created_by.eq(target.get_creator().map(|x| x as i64)),
+            created_at.eq(time),
+        ))
+        .returning(id)
+        .get_result(db)?)
+}
+
+impl RuleStore for PgStore {
+    fn insert(&self, target: &Target, time: SystemTime) -> StoreResult<i64> {
+        let mut db = self.pool.get()?;
+        insert_rule(&mut db, target, true, time)
+    }
+
+    fn insert_all(&self, targets: &[(Target, bool)], time: SystemTime) -> StoreResult<Vec<i64>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            targets
+                .iter()
+                .map(|(target, on)| insert_rule(db, target, *on, time))
+                .collect()
+        })
+    }
+
+    fn active_for_message(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        Ok(actions
+            .filter(guild_id.eq(gid.get() as i64))
+            .filter(user_id.eq(uid.get() as i64).or(user_id.is_null()))
+            .filter(expiration.is_null().or(expiration.gt(time)))
+            .filter(enabled.eq(true))
+            .order(id.asc())
+            .load::<Action>(&mut *db)?)
+    }
+
+    fn list(&self, gid: GuildId, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        Ok(actions
+            .filter(guild_id.eq(gid.get() as i64))
+            .filter(expiration.is_null().or(expiration.gt(time)))
+            .order(id.asc())
+            .load::<Action>(&mut *db)?)
+    }
+
+    fn targeting_user(&self, uid: UserId, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        Ok(actions
+            .filter(user_id.eq(uid.get() as i64))
+            .filter(expiration.is_null().or(expiration.gt(time)))
+            .order(id.asc())
+            .load::<Action>(&mut *db)?)
+    }
+
+    fn get(&self, gid: GuildId, target_id: i64) -> StoreResult<Option<Action>> {
+        let mut db = self.pool.get()?;
+        Ok(actions
+            .filter(id.eq(target_id))
+            .filter(guild_id.eq(gid.get() as i64))
+            .first::<Action>(&mut *db)
+            .optional()?)
+    }
+
+    fn update(
+        &self,
+        target_id: i64,
+        target: &Target,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let rule = actions
+                .filter(id.eq(target_id))
+                .filter(guild_id.eq(target.get_guild().get() as i64));
+            let Some(old) = rule.first::<Action>(db).optional()? else {
+                return Ok(false);
+            };
+            archive_rules(db, &[old], ArchiveReason::Edited, by, time)?;
+            diesel::update(rule)
+                .set((
+                    user_id.eq(target.get_user().map(|x| x as i64)),
+                    reactions.eq(target.get_emotes()),
+                    expiration.eq(target.get_expiration()),
+                    regex.eq(target.get_regex()),
+                    failure_count.eq(0),
+                ))
+                .execute(db)?;
+            Ok(true)
+        })
+    }
+
+    fn set_enabled(&self, gid: GuildId, target_id: i64, state: bool) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let rule = actions
+            .filter(id.eq(target_id))
+            .filter(guild_id.eq(gid.get() as i64));
+        // Resuming a rule gives it a fresh run of refusals before it's paused again
+        let count = if state {
+            diesel::update(rule)
+                .set((enabled.eq(true), failure_count.eq(0)))
+                .execute(&mut *db)?
+        } else {
+            diesel::update(rule)
+                .set(enabled.eq(false))
+                .execute(&mut *db)?
+        };
+        Ok(count > 0)
+    }
+
+    fn set_expiration(
+        &self,
+        gid: GuildId,
+        target_id: i64,
+        expires: Option<SystemTime>,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let count = diesel::update(
+            actions
+                .filter(id.eq(target_id))
+                .filter(guild_id.eq(gid.get() as i64)),
+        )
+        .set(expiration.eq(expires))
+        .execute(&mut *db)?;
+        Ok(count > 0)
+    }
+
+    fn remove(
+        &self,
+        gid: GuildId,
+        target_id: i64,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let removed = diesel::delete(
+                actions
+                    .filter(id.eq(target_id))
+                    .filter(guild_id.eq(gid.get() as i64)),
+            )
+            .returning(crate::schema::actions::all_columns)
+            .get_results::<Action>(db)?;
+            archive_rules(db, &removed, ArchiveReason::Removed, by, time)?;
+            Ok(!removed.is_empty())
+        })
+    }
+
+    fn purge(
+        &self,
+        gid: Option<GuildId>,
+        uid: Option<UserId>,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let mut query = diesel::delete(actions).into_boxed();
+            if let Some(gid) = gid {
+                query = query.filter(guild_id.eq(gid.get() as i64));
+            }
+            if let Some(uid) = uid {
+                query = query.filter(user_id.eq(uid.get() as i64));
+            }
+            let removed = query
+                .returning(crate::schema::actions::all_columns)
+                .get_results::<Action>(db)?;
+            archive_rules(db, &removed, ArchiveReason::Removed, by, time)?;
+            Ok(removed)
+        })
+    }
+
+    fn record_hits(&self, ids: &[i64], time: SystemTime) -> StoreResult<()> {
+        let mut db = self.pool.get()?;
+        diesel::update(actions.filter(id.eq_any(ids)))
+            .set((
+                hit_count.eq(hit_count + 1),
+                last_fired_at.eq(time),
+                failure_count.eq(0),
+            ))
+            .execute(&mut *db)?;
+        Ok(())
+    }
+
+    fn record_failures(&self, ids: &[i64], limit: i64) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            diesel::update(actions.filter(id.eq_any(ids)))
+                .set(failure_count.eq(failure_count + 1))
+                .execute(db)?;
+            let paused = diesel::update(
+                actions
+                    .filter(id.eq_any(ids))
+                    .filter(enabled.eq(true))
+                    .filter(failure_count.ge(limit)),
+            )
+            .set(enabled.eq(false))
+            .get_results(db)?;
+            Ok(paused)
+        })
+    }
+
+    fn delete_expired(&self, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let expired = diesel::delete(actions.filter(expiration.lt(time)))
+                .returning(crate::schema::actions::all_columns)
+                .get_results::<Action>(db)?;
+            archive_rules(db, &expired, ArchiveReason::Expired, None, time)?;
+            Ok(expired)
+        })
+    }
+
+    fn last_created(&self, gid: GuildId, creator: UserId) -> StoreResult<Option<SystemTime>> {
+        let mut db = self.pool.get()?;
+        let active: Option<SystemTime> = actions
+            .filter(guild_id.eq(gid.get() as i64))
+            .filter(created_by.eq(creator.get() as i64))
+            .select(diesel::dsl::max(created_at))
+            .first(&mut *db)?;
+        let archived: Option<SystemTime> = archive::table
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .filter(archive::created_by.eq(creator.get() as i64))
+            .select(diesel::dsl::max(archive::created_at))
+            .first(&mut *db)?;
+        Ok(active.max(archived))
+    }
+
+    fn history(
+        &self,
+        gid: GuildId,
+        offset: usize,
+        limit: usize,
+    ) -> StoreResult<Vec<ArchivedAction>> {
+        let mut db = self.pool.get()?;
+        let rows = archive::table
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .order((archive::archived_at.desc(), archive::archive_id.desc()))
+            .offset(offset.min(i64::MAX as usize) as i64)
+            .limit(limit.min(i64::MAX as usize) as i64)
+            .load::<ArchiveRow>(&mut *db)?;
+        Ok(rows.into_iter().map(ArchivedAction::from).collect())
+    }
+
+    fn history_len(&self, gid: GuildId) -> StoreResult<usize> {
+        let mut db = self.pool.get()?;
+        let count: i64 = archive::table
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .count()
+            .get_result(&mut *db)?;
+        Ok(count as usize)
+    }
+
+    fn get_archived(&self, gid: GuildId, archive_id: i64) -> StoreResult<Option<ArchivedAction>> {
+        let mut db = self.pool.get()?;
+        let row = archive::table
+            .filter(archive::archive_id.eq(archive_id))
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .first::<ArchiveRow>(&mut *db)
+            .optional()?;
+        Ok(row.map(ArchivedAction::from))
+    }
+
+    fn add_immunity(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let count = insert_into(immune::table)
+            .values(Immunity {
+                guild_id: gid.get() as i64,
+                user_id: uid.get() as i64,
+                added_by: by.map(|u| u.get() as i64),
+                added_at: time,
+            })
+            .on_conflict_do_nothing()
+            .execute(&mut *db)?;
+        Ok(count > 0)
+    }
+
+    fn remove_immunity(&self, gid: GuildId, uid: UserId) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let count = diesel::delete(
+            immune::table
+                .filter(immune::guild_id.eq(gid.get() as i64))
+                .filter(immune::user_id.eq(uid.get() as i64)),
+        )
+        .execute(&mut *db)?;
+        Ok(count > 0)
+    }
+
+    fn immune_users(&self, gid: GuildId) -> StoreResult<Vec<Immunity>> {
+        let mut db = self.pool.get()?;
+        Ok(immune::table
+            .filter(immune::guild_id.eq(gid.get() as i64))
+            .order((immune::added_at.asc(), immune::user_id.asc()))
+            .load(&mut *db)?)
+    }
+
+    fn migrate(&self) -> StoreResult<Vec<String>> {
+        let mut db = self.pool.get()?;
+        let applied = run_migrations(&mut *db, MIGRATIONS)?;
+        if applied.iter().any(|v| v == STRUCTURED_REACTIONS) {
+            regroup_legacy_reactions(&mut db)?;
+        }
+        Ok(applied)
+    }
+}
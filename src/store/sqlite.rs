@@ -0,0 +1,747 @@
+use super::{run_migrations, RuleStore, StoreError, StoreResult};
+use crate::action::{Action, ArchiveReason, ArchivedAction};
+use crate::immunity::Immunity;
+use crate::target::Target;
+use diesel::insert_into;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, CustomizeConnection, Pool};
+use diesel::sqlite::SqliteConnection;
+use diesel_migrations::{embed_migrations, EmbeddedMigrations};
+use serenity::model::id::{GuildId, UserId};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// SQLite has no arrays or timestamps, so reactions are stored space
+/// separated and expiration as seconds since the unix epoch.
+mod schema {
+    diesel::table! {
+        actions (id) {
+            id -> BigInt,
+            guild_id -> BigInt,
+            user_id -> Nullable<BigInt>,
+            regex -> Nullable<Text>,
+            reactions -> Text,
+            expiration -> Nullable<BigInt>,
+            enabled -> Bool,
+            created_by -> Nullable<BigInt>,
+            created_at -> Nullable<BigInt>,
+            hit_count -> BigInt,
+            last_fired_at -> Nullable<BigInt>,
+            failure_count -> BigInt,
+        }
+    }
+
+    diesel::table! {
+        archived_actions (archive_id) {
+            archive_id -> BigInt,
+            action_id -> BigInt,
+            guild_id -> BigInt,
+            user_id -> Nullable<BigInt>,
+            regex -> Nullable<Text>,
+            reactions -> Text,
+            expiration -> Nullable<BigInt>,
+            enabled -> Bool,
+            created_by -> Nullable<BigInt>,
+            created_at -> Nullable<BigInt>,
+            hit_count -> BigInt,
+            last_fired_at -> Nullable<BigInt>,
+            reason -> Text,
+            archived_by -> Nullable<BigInt>,
+            archived_at -> BigInt,
+        }
+    }
+
+    diesel::table! {
+        immune_users (guild_id, user_id) {
+            guild_id -> BigInt,
+            user_id -> BigInt,
+            added_by -> Nullable<BigInt>,
+            added_at -> BigInt,
+        }
+    }
+}
+
+use schema::actions::dsl::*;
+use schema::archived_actions as archive;
+use schema::immune_users as immune;
+
+#[derive(Queryable)]
+struct SqliteAction {
+    id: i64,
+    guild_id: i64,
+    user_id: Option<i64>,
+    regex: Option<String>,
+    reactions: String,
+    expiration: Option<i64>,
+    enabled: bool,
+    created_by: Option<i64>,
+    created_at: Option<i64>,
+    hit_count: i64,
+    last_fired_at: Option<i64>,
+    failure_count: i64,
+}
+
+impl From<SqliteAction> for Action {
+    fn from(row: SqliteAction) -> Self {
+        Action {
+            id: row.id,
+            guild_id: row.guild_id,
+            user_id: row.user_id,
+            regex: row.regex,
+            reactions: row.reactions.split(' ').map(|r| r.to_string()).collect(),
+            expiration: row.expiration.map(from_unix),
+            enabled: row.enabled,
+            created_by: row.created_by,
+            created_at: row.created_at.map(from_unix),
+            hit_count: row.hit_count,
+            last_fired_at: row.last_fired_at.map(from_unix),
+            failure_count: row.failure_count,
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct SqliteArchiveRow {
+    archive_id: i64,
+    action_id: i64,
+    guild_id: i64,
+    user_id: Option<i64>,
+    regex: Option<String>,
+    reactions: String,
+    expiration: Option<i64>,
+    enabled: bool,
+    created_by: Option<i64>,
+    created_at: Option<i64>,
+    hit_count: i64,
+    last_fired_at: Option<i64>,
+    reason: String,
+    archived_by: Option<i64>,
+    archived_at: i64,
+}
+
+impl From<SqliteArchiveRow> for ArchivedAction {
+    fn from(row: SqliteArchiveRow) -> Self {
+        ArchivedAction {
+            archive_id: row.archive_id,
+            action: SqliteAction {
+                id: row.action_id,
+                guild_id: row.guild_id,
+                user_id: row.user_id,
+                regex: row.regex,
+                reactions: row.reactions,
+                expiration: row.expiration,
+                enabled: row.enabled,
+                created_by: row.created_by,
+                created_at: row.created_at,
+                hit_count: row.hit_count,
+                last_fired_at: row.last_fired_at,
+                failure_count: 0,
+            }
+            .into(),
+            // The column is constrained to the known reasons
+            reason: row.reason.parse().unwrap_or(ArchiveReason::Removed),
+            archived_by: row.archived_by,
+            archived_at: from_unix(row.archived_at),
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct SqliteImmunity {
+    guild_id: i64,
+    user_id: i64,
+    added_by: Option<i64>,
+    added_at: i64,
+}
+
+impl From<SqliteImmunity> for Immunity {
+    fn from(row: SqliteImmunity) -> Self {
+        Immunity {
+            guild_id: row.guild_id,
+            user_id: row.user_id,
+            added_by: row.added_by,
+            added_at: from_unix(row.added_at),
+        }
+    }
+}
+
+/// Copy rules into the archive.
+fn archive_rules(
+    db: &mut SqliteConnection,
+    rules: &[Action],
+    reason: ArchiveReason,
+    by: Option<UserId>,
+    time: SystemTime,
+) -> QueryResult<usize> {
+    let rows: Vec<_> = rules
+        .iter()
+        .map(|a| {
+            (
+                archive::action_id.eq(a.id),
+                archive::guild_id.eq(a.guild_id),
+                archive::user_id.eq(a.user_id),
+                archive::regex.eq(a.regex.clone()),
+                archive::reactions.eq(a.reactions.join(" ")),
+                archive::expiration.eq(a.expiration.map(to_unix)),
+                archive::enabled.eq(a.enabled),
+                archive::created_by.eq(a.created_by),
+                archive::created_at.eq(a.created_at.map(to_unix)),
+                archive::hit_count.eq(a.hit_count),
+                archive::last_fired_at.eq(a.last_fired_at.map(to_unix)),
+                archive::reason.eq(reason.as_str()),
+                archive::archived_by.eq(by.map(|u| u.get() as i64)),
+                archive::archived_at.eq(to_unix(time)),
+            )
+        })
+        .collect();
+    insert_into(archive::table).values(rows).execute(db)
+}
+
+fn to_unix(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs() as i64
+}
+
+fn from_unix(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_sqlite");
+
+/// Milliseconds a connection waits on another's write lock before giving up.
+const BUSY_TIMEOUT: u32 = 5000;
+
+/// Sets up each pooled connection so they can share the file: writers wait
+/// on each other instead of failing with "database is locked", and readers
+/// don't block the writer.
+#[derive(Debug)]
+struct SharedFile;
+
+impl CustomizeConnection<SqliteConnection, diesel::r2d2::Error> for SharedFile {
+    fn on_acquire(&self, conn: &mut SqliteConnection) -> Result<(), diesel::r2d2::Error> {
+        diesel::sql_query(format!("PRAGMA busy_timeout = {}", BUSY_TIMEOUT))
+            .execute(conn)
+            .and_then(|_| diesel::sql_query("PRAGMA journal_mode = WAL").execute(conn))
+            .map(|_| ())
+            .map_err(diesel::r2d2::Error::QueryError)
+    }
+}
+
+pub struct SqliteStore {
+    pool: Pool<ConnectionManager<SqliteConnection>>,
+}
+
+impl SqliteStore {
+    pub fn new(pool: Pool<ConnectionManager<SqliteConnection>>) -> Self {
+        Self { pool }
+    }
+
+    /// Open the database file with a pool of up to `pool_size` connections.
+    pub fn establish(path: &str, pool_size: u32) -> StoreResult<Self> {
+        let pool = Pool::builder()
+            .max_size(pool_size)
+            .connection_customizer(Box::new(SharedFile))
+            .build(ConnectionManager::new(path))?;
+        Ok(Self::new(pool))
+    }
+}
+
+/// Add a rule, enabled or paused, returning its ID.
+fn insert_rule(
+    db: &mut SqliteConnection,
+    target: &Target,
+    on: bool,
+    time: SystemTime,
+) -> StoreResult<i64> {
+    Ok(insert_into(actions)
+        .values((
+            guild_id.eq(target.get_guild().get() as i64),
+            user_id.eq(target.get_user().map(|x| x as i64)),
+            reactions.eq(target.get_emotes().join(" ")),
+            expiration.eq(target.get_expiration().map(to_unix)),
+            regex.eq(target.get_regex()),
+            enabled.eq(on),
+            created_by.eq(target.get_creator().map(|x| x as i64)),
+            created_at.eq(to_unix(time)),
+        ))
+        .returning(id)
+        .get_result(db)?)
+}
+
+impl RuleStore for SqliteStore {
+    fn insert(&self, target: &Target, time: SystemTime) -> StoreResult<i64> {
+        let mut db = self.pool.get()?;
+        insert_rule(&mut db, target, true, time)
+    }
+
+    fn insert_all(&self, targets: &[(Target, bool)], time: SystemTime) -> StoreResult<Vec<i64>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            targets
+                .iter()
+                .map(|(target, on)| insert_rule(db, target, *on, time))
+                .collect()
+        })
+    }
+
+    fn active_for_message(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        let rows = actions
+            .filter(guild_id.eq(gid.get() as i64))
+            .filter(user_id.eq(uid.get() as i64).or(user_id.is_null()))
+            .filter(expiration.is_null().or(expiration.gt(to_unix(time))))
+            .filter(enabled.eq(true))
+            .order(id.asc())
+            .load::<SqliteAction>(&mut *db)?;
+        Ok(rows.into_iter().map(Action::from).collect())
+    }
+
+    fn list(&self, gid: GuildId, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        let rows = actions
+            .filter(guild_id.eq(gid.get() as i64))
+            .filter(expiration.is_null().or(expiration.gt(to_unix(time))))
+            .order(id.asc())
+            .load::<SqliteAction>(&mut *db)?;
+        Ok(rows.into_iter().map(Action::from).collect())
+    }
+
+    fn targeting_user(&self, uid: UserId, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        let rows = actions
+            .filter(user_id.eq(uid.get() as i64))
+            .filter(expiration.is_null().or(expiration.gt(to_unix(time))))
+            .order(id.asc())
+            .load::<SqliteAction>(&mut *db)?;
+        Ok(rows.into_iter().map(Action::from).collect())
+    }
+
+    fn get(&self, gid: GuildId, target_id: i64) -> StoreResult<Option<Action>> {
+        let mut db = self.pool.get()?;
+        let row = actions
+            .filter(id.eq(target_id))
+            .filter(guild_id.eq(gid.get() as i64))
+            .first::<SqliteAction>(&mut *db)
+            .optional()?;
+        Ok(row.map(Action::from))
+    }
+
+    fn update(
+        &self,
+        target_id: i64,
+        target: &Target,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let rule = actions
+                .filter(id.eq(target_id))
+                .filter(guild_id.eq(target.get_guild().get() as i64));
+            let Some(old) = rule.first::<SqliteAction>(db).optional()? else {
+                return Ok(false);
+            };
+            archive_rules(db, &[old.into()], ArchiveReason::Edited, by, time)?;
+            diesel::update(rule)
+                .set((
+                    user_id.eq(target.get_user().map(|x| x as i64)),
+                    reactions.eq(target.get_emotes().join(" ")),
+                    expiration.eq(target.get_expiration().map(to_unix)),
+                    regex.eq(target.get_regex()),
+                    failure_count.eq(0),
+                ))
+                .execute(db)?;
+            Ok(true)
+        })
+    }
+
+    fn set_enabled(&self, gid: GuildId, target_id: i64, state: bool) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let rule = actions
+            .filter(id.eq(target_id))
+            .filter(guild_id.eq(gid.get() as i64));
+        // Resuming a rule gives it a fresh run of refusals before it's paused again
+        let count = if state {
+            diesel::update(rule)
+                .set((enabled.eq(true), failure_count.eq(0)))
+                .execute(&mut *db)?
+        } else {
+            diesel::update(rule)
+                .set(enabled.eq(false))
+                .execute(&mut *db)?
+        };
+        Ok(count > 0)
+    }
+
+    fn set_expiration(
+        &self,
+        gid: GuildId,
+        target_id: i64,
+        expires: Option<SystemTime>,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let count = diesel::update(
+            actions
+                .filter(id.eq(target_id))
+                .filter(guild_id.eq(gid.get() as i64)),
+        )
+        .set(expiration.eq(expires.map(to_unix)))
+        .execute(&mut *db)?;
+        Ok(count > 0)
+    }
+
+    fn remove(
+        &self,
+        gid: GuildId,
+        target_id: i64,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let removed: Vec<Action> = diesel::delete(
+                actions
+                    .filter(id.eq(target_id))
+                    .filter(guild_id.eq(gid.get() as i64)),
+            )
+            .returning(schema::actions::all_columns)
+            .get_results::<SqliteAction>(db)?
+            .into_iter()
+            .map(Action::from)
+            .collect();
+            archive_rules(db, &removed, ArchiveReason::Removed, by, time)?;
+            Ok(!removed.is_empty())
+        })
+    }
+
+    fn purge(
+        &self,
+        gid: Option<GuildId>,
+        uid: Option<UserId>,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let mut query = diesel::delete(actions).into_boxed();
+            if let Some(gid) = gid {
+                query = query.filter(guild_id.eq(gid.get() as i64));
+            }
+            if let Some(uid) = uid {
+                query = query.filter(user_id.eq(uid.get() as i64));
+            }
+            let removed: Vec<Action> = query
+                .returning(schema::actions::all_columns)
+                .get_results::<SqliteAction>(db)?
+                .into_iter()
+                .map(Action::from)
+                .collect();
+            archive_rules(db, &removed, ArchiveReason::Removed, by, time)?;
+            Ok(removed)
+        })
+    }
+
+    fn record_hits(&self, ids: &[i64], time: SystemTime) -> StoreResult<()> {
+        let mut db = self.pool.get()?;
+        diesel::update(actions.filter(id.eq_any(ids)))
+            .set((
+                hit_count.eq(hit_count + 1),
+                last_fired_at.eq(to_unix(time)),
+                failure_count.eq(0),
+            ))
+            .execute(&mut *db)?;
+        Ok(())
+    }
+
+    fn record_failures(&self, ids: &[i64], limit: i64) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            diesel::update(actions.filter(id.eq_any(ids)))
+                .set(failure_count.eq(failure_count + 1))
+                .execute(db)?;
+            let paused = diesel::update(
+                actions
+                    .filter(id.eq_any(ids))
+                    .filter(enabled.eq(true))
+                    .filter(failure_count.ge(limit)),
+            )
+            .set(enabled.eq(false))
+            .returning(schema::actions::all_columns)
+            .get_results::<SqliteAction>(db)?;
+            Ok(paused.into_iter().map(Action::from).collect())
+        })
+    }
+
+    fn delete_expired(&self, time: SystemTime) -> StoreResult<Vec<Action>> {
+        let mut db = self.pool.get()?;
+        db.transaction::<_, StoreError, _>(|db| {
+            let expired: Vec<Action> = diesel::delete(actions.filter(expiration.lt(to_unix(time))))
+                .returning(schema::actions::all_columns)
+                .get_results::<SqliteAction>(db)?
+                .into_iter()
+                .map(Action::from)
+                .collect();
+            archive_rules(db, &expired, ArchiveReason::Expired, None, time)?;
+            Ok(expired)
+        })
+    }
+
+    fn last_created(&self, gid: GuildId, creator: UserId) -> StoreResult<Option<SystemTime>> {
+        let mut db = self.pool.get()?;
+        let active: Option<i64> = actions
+            .filter(guild_id.eq(gid.get() as i64))
+            .filter(created_by.eq(creator.get() as i64))
+            .select(diesel::dsl::max(created_at))
+            .first(&mut *db)?;
+        let archived: Option<i64> = archive::table
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .filter(archive::created_by.eq(creator.get() as i64))
+            .select(diesel::dsl::max(archive::created_at))
+            .first(&mut *db)?;
+        Ok(active.max(archived).map(from_unix))
+    }
+
+    fn history(
+        &self,
+        gid: GuildId,
+        offset: usize,
+        limit: usize,
+    ) -> StoreResult<Vec<ArchivedAction>> {
+        let mut db = self.pool.get()?;
+        let rows = archive::table
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .order((archive::archived_at.desc(), archive::archive_id.desc()))
+            .offset(offset.min(i64::MAX as usize) as i64)
+            .limit(limit.min(i64::MAX as usize) as i64)
+            .load::<SqliteArchiveRow>(&mut *db)?;
+        Ok(rows.into_iter().map(ArchivedAction::from).collect())
+    }
+
+    fn history_len(&self, gid: GuildId) -> StoreResult<usize> {
+        let mut db = self.pool.get()?;
+        let count: i64 = archive::table
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .count()
+            .get_result(&mut *db)?;
+        Ok(count as usize)
+    }
+
+    fn get_archived(&self, gid: GuildId, archive_id: i64) -> StoreResult<Option<ArchivedAction>> {
+        let mut db = self.pool.get()?;
+        let row = archive::table
+            .filter(archive::archive_id.eq(archive_id))
+            .filter(archive::guild_id.eq(gid.get() as i64))
+            .first::<SqliteArchiveRow>(&mut *db)
+            .optional()?;
+        Ok(row.map(ArchivedAction::from))
+    }
+
+    fn add_immunity(
+        &self,
+        gid: GuildId,
+        uid: UserId,
+        by: Option<UserId>,
+        time: SystemTime,
+    ) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let count = insert_into(immune::table)
+            .values((
+                immune::guild_id.eq(gid.get() as i64),
+                immune::user_id.eq(uid.get() as i64),
+                immune::added_by.eq(by.map(|u| u.get() as i64)),
+                immune::added_at.eq(to_unix(time)),
+            ))
+            .on_conflict_do_nothing()
+            .execute(&mut *db)?;
+        Ok(count > 0)
+    }
+
+    fn remove_immunity(&self, gid: GuildId, uid: UserId) -> StoreResult<bool> {
+        let mut db = self.pool.get()?;
+        let count = diesel::delete(
+            immune::table
+                .filter(immune::guild_id.eq(gid.get() as i64))
+                .filter(immune::user_id.eq(uid.get() as i64)),
+        )
+        .execute(&mut *db)?;
+        Ok(count > 0)
+    }
+
+    fn immune_users(&self, gid: GuildId) -> StoreResult<Vec<Immunity>> {
+        let mut db = self.pool.get()?;
+        let rows = immune::table
+            .filter(immune::guild_id.eq(gid.get() as i64))
+            .order((immune::added_at.asc(), immune::user_id.asc()))
+            .load::<SqliteImmunity>(&mut *db)?;
+        Ok(rows.into_iter().map(Immunity::from).collect())
+    }
+
+    fn migrate(&self) -> StoreResult<Vec<String>> {
+        let mut db = self.pool.get()?;
+        run_migrations(&mut *db, MIGRATIONS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::StoreError;
+
+    fn store() -> SqliteStore {
+        // Every connection to :memory: is a separate database, so stick to one
+        let store = SqliteStore::establish(":memory:", 1).unwrap();
+        store.migrate().unwrap();
+        store
+    }
+
+    #[test]
+    fn connections_share_file() {
+        #[derive(QueryableByName)]
+        struct Mode {
+            #[diesel(sql_type = diesel::sql_types::Text)]
+            journal_mode: String,
+        }
+
+        let path = std::env::temp_dir().join(format!("bread-bot-{}.db", std::process::id()));
+        let store = SqliteStore::establish(path.to_str().unwrap(), 2).unwrap();
+        store.migrate().unwrap();
+        let mut db = store.pool.get().unwrap();
+        let mode = diesel::sql_query("PRAGMA journal_mode")
+            .get_result::<Mode>(&mut *db)
+            .unwrap();
+        assert_eq!(mode.journal_mode, "wal");
+        drop(db);
+        drop(store);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn insert_all_sets_enabled() {
+        let store = store();
+        let target = Target::builder()
+            .set_guild(GuildId::new(1))
+            .set_regex("bread")
+            .set_emotes("🍞")
+            .build()
+            .unwrap();
+        let now = SystemTime::now();
+        let ids = store
+            .insert_all(&[(target.clone(), true), (target, false)], now)
+            .unwrap();
+        let listed = store.list(GuildId::new(1), now).unwrap();
+        assert_eq!(listed.iter().map(|a| a.id).collect::<Vec<_>>(), ids);
+        assert_eq!(
+            listed.iter().map(|a| a.enabled).collect::<Vec<_>>(),
+            [true, false]
+        );
+    }
+
+    #[test]
+    fn last_created_counts_archive() {
+        let store = store();
+        let creator = UserId::new(7);
+        let target = Target::builder()
+            .set_guild(GuildId::new(1))
+            .set_regex("bread")
+            .set_emotes("🍞")
+            .set_creator(creator)
+            .build()
+            .unwrap();
+        let then = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let rule = store.insert(&target, then).unwrap();
+        assert!(store.remove(GuildId::new(1), rule, None, then).unwrap());
+        store.insert(&target, UNIX_EPOCH).unwrap();
+
+        assert_eq!(
+            store.last_created(GuildId::new(1), creator).unwrap(),
+            Some(then)
+        );
+        assert_eq!(store.last_created(GuildId::new(2), creator).unwrap(), None);
+        assert_eq!(
+            store.last_created(GuildId::new(1), UserId::new(8)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = store();
+        let target = Target::builder()
+            .set_guild(GuildId::new(1))
+            .set_user(UserId::new(5))
+            .set_emotes("🇺🇸 <:bread:1234>")
+            .set_expiration(10)
+            .build()
+            .unwrap();
+        let new_id = store.insert(&target, SystemTime::now()).unwrap();
+
+        let now = SystemTime::now();
+        let found = store
+            .active_for_message(GuildId::new(1), UserId::new(5), now)
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, new_id);
+        assert_eq!(found[0].reactions, ["🇺🇸", "<:bread:1234>"]);
+
+        store.record_hits(&[new_id], now).unwrap();
+        let found = store.get(GuildId::new(1), new_id).unwrap().unwrap();
+        assert_eq!(found.hit_count, 1);
+        assert!(found.last_fired_at.is_some());
+
+        let paused = store.record_failures(&[new_id], 1).unwrap();
+        assert_eq!(paused.len(), 1);
+        assert_eq!(paused[0].failure_count, 1);
+        assert!(!paused[0].enabled);
+        assert!(store.set_enabled(GuildId::new(1), new_id, true).unwrap());
+
+        assert!(store.set_enabled(GuildId::new(1), new_id, false).unwrap());
+        assert!(store
+            .active_for_message(GuildId::new(1), UserId::new(5), now)
+            .unwrap()
+            .is_empty());
+
+        let later = now + Duration::from_secs(3600);
+        assert_eq!(store.delete_expired(later).unwrap().len(), 1);
+        assert!(store.get(GuildId::new(1), new_id).unwrap().is_none());
+
+        let history = store.history(GuildId::new(1), 0, 10).unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(store.history_len(GuildId::new(1)).unwrap(), 1);
+        assert!(store.history(GuildId::new(1), 1, 10).unwrap().is_empty());
+        assert_eq!(history[0].reason, ArchiveReason::Expired);
+        assert_eq!(history[0].action.id, new_id);
+        assert_eq!(history[0].action.reactions, ["🇺🇸", "<:bread:1234>"]);
+
+        let uid = UserId::new(5);
+        assert!(store.add_immunity(GuildId::new(1), uid, None, now).unwrap());
+        assert!(!store.add_immunity(GuildId::new(1), uid, None, now).unwrap());
+        assert_eq!(store.immune_users(GuildId::new(1)).unwrap()[0].user_id, 5);
+        assert!(store.remove_immunity(GuildId::new(1), uid).unwrap());
+    }
+
+    #[test]
+    fn newer_schema_refused() {
+        use diesel::connection::SimpleConnection;
+
+        let store = store();
+        store
+            .pool
+            .get()
+            .unwrap()
+            .batch_execute(
+                "INSERT INTO __diesel_schema_migrations (version) VALUES ('99999999999999')",
+            )
+            .unwrap();
+        assert!(matches!(
+            store.migrate(),
+            Err(StoreError::SchemaTooNew(v)) if v == ["99999999999999"]
+        ));
+    }
+}
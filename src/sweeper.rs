@@ -0,0 +1,60 @@
+use crate::store::{self, RuleStore};
+use serenity::http::Http;
+use serenity::model::id::{ChannelId, GuildId};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Background task that periodically deletes expired rules, so that quiet
+/// guilds don't keep dead rows around and busy ones don't pay for a delete on
+/// every message.
+pub struct Sweeper {
+    store: Arc<dyn RuleStore>,
+    interval: Duration,
+    announce: HashMap<GuildId, ChannelId>,
+}
+
+impl Sweeper {
+    /// Expired rules are announced in the channel listed for their guild in
+    /// `announce`, if any.
+    pub fn new(
+        store: Arc<dyn RuleStore>,
+        interval: Duration,
+        announce: HashMap<GuildId, ChannelId>,
+    ) -> Self {
+        Self {
+            store,
+            interval,
+            announce,
+        }
+    }
+
+    pub async fn run(self, http: Arc<Http>) {
+        let mut ticker = tokio::time::interval(self.interval);
+        loop {
+            ticker.tick().await;
+            self.sweep(&http).await;
+        }
+    }
+
+    async fn sweep(&self, http: &Http) {
+        let now = SystemTime::now();
+        let expired = match store::blocking(&self.store, move |s| s.delete_expired(now)).await {
+            Ok(expired) => expired,
+            Err(e) => {
+                println!("Error deleting expired targets: {}", e);
+                return;
+            }
+        };
+
+        for action in expired {
+            let gid = GuildId::new(action.guild_id as u64);
+            if let Some(channel) = self.announce.get(&gid) {
+                let content = format!("Target #{} expired: {}", action.id, action.summary());
+                if let Err(why) = channel.say(http, content).await {
+                    println!("Error announcing expired target: {:?}", why);
+                }
+            }
+        }
+    }
+}
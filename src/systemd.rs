@@ -0,0 +1,44 @@
+use sd_notify::NotifyState;
+use serenity::gateway::{ConnectionStage, ShardManager};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Tell systemd the bot is connected. Does nothing outside of a
+/// `Type=notify` service.
+pub fn notify_ready() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        println!("Error notifying systemd: {}", e);
+    }
+}
+
+pub fn notify_stopping() {
+    if let Err(e) = sd_notify::notify(false, &[NotifyState::Stopping]) {
+        println!("Error notifying systemd: {}", e);
+    }
+}
+
+/// Ping systemd's watchdog at half its interval, but only while every shard
+/// is connected, so that a bot that has lost discord for too long gets
+/// restarted. Returns straight away when the watchdog isn't enabled.
+pub async fn watchdog(shard_manager: Arc<ShardManager>) {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return;
+    }
+    // A watchdog of a microsecond or less still needs a non-zero period
+    let mut ticker = tokio::time::interval(Duration::from_micros((usec / 2).max(1)));
+    loop {
+        ticker.tick().await;
+        let runners = shard_manager.runners.lock().await;
+        let connected = !runners.is_empty()
+            && runners
+                .values()
+                .all(|r| r.stage == ConnectionStage::Connected);
+        drop(runners);
+        if connected {
+            if let Err(e) = sd_notify::notify(false, &[NotifyState::Watchdog]) {
+                println!("Error pinging systemd watchdog: {}", e);
+            }
+        }
+    }
+}
@@ -1,13 +1,33 @@
-use regex::Regex;
+use crate::action::Action;
+use crate::emoji::{parse_emotes, BadEmoji};
+use crate::reaction_set::MAX_REACTIONS;
+use regex::{Regex, RegexBuilder};
 use serenity::model::id::{GuildId, UserId};
 use std::error::Error;
 use std::fmt;
 use std::time::{Duration, SystemTime};
 
-#[derive(Debug)]
+/// Longest regex a rule may have, in characters.
+pub const MAX_PATTERN_LEN: usize = 200;
+
+/// Limits on the compiled program and the lazy DFA's cache for a rule's regex,
+/// in bytes, so that a single rule can't eat the bot's memory.
+const REGEX_SIZE_LIMIT: usize = 256 * 1024;
+const REGEX_DFA_SIZE_LIMIT: usize = 1024 * 1024;
+
+/// Compile a rule's regex within the size limits.
+pub fn compile_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_DFA_SIZE_LIMIT)
+        .build()
+}
+
+#[derive(Debug, Clone)]
 pub struct Target {
     guild: GuildId,
     user: Option<u64>,
+    creator: Option<u64>,
     emotes: Vec<String>,
     expiration: Option<SystemTime>,
     regex: Option<String>,
@@ -26,6 +46,10 @@ impl Target {
         self.user
     }
 
+    pub fn get_creator(&self) -> Option<u64> {
+        self.creator
+    }
+
     pub fn get_emotes(&self) -> &[String] {
         &self.emotes
     }
@@ -42,16 +66,54 @@ impl Target {
 #[derive(Debug)]
 pub enum TargetBuilderError {
     MissingUserAndRegex,
+    AlreadyExpired,
+    /// The user opted out of being targeted, or a moderator exempted them.
+    ImmuneUser(u64),
     BadRegex(regex::Error),
+    /// The regex is longer than `MAX_PATTERN_LEN`, holding its length.
+    RegexTooLong(usize),
+    /// The regex compiles to more than the size limits allow.
+    RegexTooBig,
+    /// The regex matches empty text, and so every message, without that being
+    /// confirmed with `allow_empty_match`.
+    RegexMatchesEverything,
+    BadEmote(BadEmoji),
+    /// More emoji than discord allows on a message, holding how many.
+    TooManyEmotes(usize),
     EmptyField(String),
+    /// A user or guild ID of 0, naming the field.
+    ZeroId(&'static str),
+    /// The expiration is too far out to be represented.
+    ExpirationOutOfRange,
 }
 
 impl fmt::Display for TargetBuilderError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match &self {
             TargetBuilderError::MissingUserAndRegex => write!(f, "Missing user and regex"),
+            TargetBuilderError::AlreadyExpired => write!(f, "Expiration is in the past"),
+            TargetBuilderError::ImmuneUser(uid) => write!(f, "User {} can't be targeted", uid),
             TargetBuilderError::BadRegex(_) => write!(f, "Regex was invalid"),
+            TargetBuilderError::RegexTooLong(len) => write!(
+                f,
+                "Regex is {} characters long, the most allowed is {}",
+                len, MAX_PATTERN_LEN
+            ),
+            TargetBuilderError::RegexTooBig => write!(f, "Regex is too complex"),
+            TargetBuilderError::RegexMatchesEverything => {
+                write!(f, "Regex matches every message")
+            }
+            TargetBuilderError::BadEmote(e) => write!(f, "{}", e),
+            TargetBuilderError::TooManyEmotes(n) => write!(
+                f,
+                "{} emoji is more than the {} discord allows on a message",
+                n, MAX_REACTIONS
+            ),
             TargetBuilderError::EmptyField(s) => write!(f, "{}", s),
+            TargetBuilderError::ZeroId(field) => write!(f, "{} can't be 0", field),
+            TargetBuilderError::ExpirationOutOfRange => {
+                write!(f, "Expiration is too far in the future")
+            }
         }
     }
 }
@@ -62,12 +124,32 @@ impl Error for TargetBuilderError {}
 pub struct TargetBuilder {
     guild: Option<GuildId>,
     user: Option<u64>,
+    creator: Option<u64>,
     emotes: Option<String>,
     expiration: Option<SystemTime>,
     regex: Option<String>,
+    immune: Vec<u64>,
+    allow_empty_match: bool,
+    /// The regex of the rule being edited, which was accepted before.
+    accepted_regex: Option<String>,
 }
 
 impl TargetBuilder {
+    /// Start from an existing rule so that only the changed fields need to be set.
+    pub fn from_action(action: &Action) -> TargetBuilder {
+        TargetBuilder {
+            guild: Some(GuildId::new(action.guild_id as u64)),
+            user: action.user_id.map(|uid| uid as u64),
+            creator: action.created_by.map(|uid| uid as u64),
+            emotes: Some(action.reactions.join(" ")),
+            expiration: action.expiration,
+            regex: action.regex.clone(),
+            immune: Vec::new(),
+            allow_empty_match: false,
+            accepted_regex: action.regex.clone(),
+        }
+    }
+
     pub fn set_guild(mut self, gid: GuildId) -> TargetBuilder {
         self.guild = Some(gid);
         self
@@ -78,6 +160,12 @@ impl TargetBuilder {
         self
     }
 
+    /// The user that created the rule.
+    pub fn set_creator(mut self, uid: UserId) -> TargetBuilder {
+        self.creator = Some(uid.get());
+        self
+    }
+
     pub fn set_emotes(mut self, emotes: &str) -> TargetBuilder {
         self.emotes = Some(emotes.to_owned());
         self
@@ -92,11 +180,39 @@ impl TargetBuilder {
         self
     }
 
+    /// Set an absolute expiration time, e.g. to keep the one from an existing rule.
+    pub fn set_expiration_time(mut self, expiration: SystemTime) -> TargetBuilder {
+        self.expiration = Some(expiration);
+        self
+    }
+
     pub fn set_regex(mut self, regex: &str) -> TargetBuilder {
         self.regex = Some(regex.to_owned());
         self
     }
 
+    /// Users the rule may not single out, see `immunity`.
+    pub fn set_immune(mut self, users: &[UserId]) -> TargetBuilder {
+        self.immune = users.iter().map(|u| u.get()).collect();
+        self
+    }
+
+    /// Accept a regex that matches empty text, and so every message.
+    pub fn allow_empty_match(mut self, allow: bool) -> TargetBuilder {
+        self.allow_empty_match = allow;
+        self
+    }
+
+    pub fn clear_user(mut self) -> TargetBuilder {
+        self.user = None;
+        self
+    }
+
+    pub fn clear_regex(mut self) -> TargetBuilder {
+        self.regex = None;
+        self
+    }
+
     pub fn build(self) -> Result<Target, TargetBuilderError> {
         if self.user.is_none() && self.regex.is_none() {
             return Err(TargetBuilderError::MissingUserAndRegex);
@@ -106,28 +222,102 @@ impl TargetBuilder {
                 "No Guild provided".to_string(),
             ));
         }
-        if self.emotes.is_none() {
+        let emotes = match &self.emotes {
+            Some(emotes) => parse_emotes(emotes).map_err(TargetBuilderError::BadEmote)?,
+            None => Vec::new(),
+        };
+        if emotes.is_empty() {
             return Err(TargetBuilderError::EmptyField(
                 "No Emotes provided".to_string(),
             ));
         }
+        // A rule that can't fit on a message would never fire
+        if emotes.len() > MAX_REACTIONS {
+            return Err(TargetBuilderError::TooManyEmotes(emotes.len()));
+        }
+        if let Some(user) = self.user.filter(|u| self.immune.contains(u)) {
+            return Err(TargetBuilderError::ImmuneUser(user));
+        }
+        if self.expiration.is_some_and(|e| e <= SystemTime::now()) {
+            return Err(TargetBuilderError::AlreadyExpired);
+        }
         if let Some(regex) = &self.regex {
-            if let Err(e) = Regex::new(regex.as_ref()) {
-                return Err(TargetBuilderError::BadRegex(e));
+            let len = regex.chars().count();
+            if len > MAX_PATTERN_LEN {
+                return Err(TargetBuilderError::RegexTooLong(len));
+            }
+            let compiled = compile_regex(regex).map_err(|e| match e {
+                regex::Error::CompiledTooBig(_) => TargetBuilderError::RegexTooBig,
+                e => TargetBuilderError::BadRegex(e),
+            })?;
+            // Editing a rule that was already accepted shouldn't need confirming again
+            if compiled.is_match("")
+                && !self.allow_empty_match
+                && self.accepted_regex.as_ref() != Some(regex)
+            {
+                return Err(TargetBuilderError::RegexMatchesEverything);
             }
         }
 
         Ok(Target {
             guild: self.guild.unwrap(),
             user: self.user,
-            emotes: self
-                .emotes
-                .unwrap()
-                .chars()
-                .map(|c| c.to_string())
-                .collect(),
+            creator: self.creator,
+            emotes: emotes.iter().map(|e| e.to_string()).collect(),
             expiration: self.expiration,
             regex: self.regex,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regex_rule(regex: &str) -> TargetBuilder {
+        Target::builder()
+            .set_guild(GuildId::new(1))
+            .set_emotes("🍞")
+            .set_regex(regex)
+    }
+
+    #[test]
+    fn emote_limit() {
+        let letters: Vec<String> = ('\u{1f1e6}'..='\u{1f1ff}').map(String::from).collect();
+        let fits = regex_rule("bread").set_emotes(&letters[..MAX_REACTIONS].join(" "));
+        assert!(fits.build().is_ok());
+        let over = regex_rule("bread").set_emotes(&letters[..MAX_REACTIONS + 1].join(" "));
+        assert!(matches!(
+            over.build(),
+            Err(TargetBuilderError::TooManyEmotes(21))
+        ));
+    }
+
+    #[test]
+    fn regex_limits() {
+        assert!(regex_rule("bread").build().is_ok());
+        assert!(matches!(
+            regex_rule(&"a".repeat(MAX_PATTERN_LEN + 1)).build(),
+            Err(TargetBuilderError::RegexTooLong(len)) if len == MAX_PATTERN_LEN + 1
+        ));
+        assert!(matches!(
+            regex_rule(r"\w{1000}\w{1000}").build(),
+            Err(TargetBuilderError::RegexTooBig)
+        ));
+        assert!(matches!(
+            regex_rule("(").build(),
+            Err(TargetBuilderError::BadRegex(_))
+        ));
+
+        for everything in [".*", "a?", "^"] {
+            assert!(matches!(
+                regex_rule(everything).build(),
+                Err(TargetBuilderError::RegexMatchesEverything)
+            ));
+            assert!(regex_rule(everything)
+                .allow_empty_match(true)
+                .build()
+                .is_ok());
+        }
+    }
+}